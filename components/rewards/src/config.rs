@@ -0,0 +1,753 @@
+use crate::bindings::host::config_var;
+use crate::policy::{AccountFilter, BelowMinimum, RewardPolicy};
+use crate::provider::{ChainProvider, RpcConfig};
+use crate::sources::{
+    contract_accounts::{ContractAccountSource, ContractAccounts},
+    erc1155::Erc1155Source,
+    erc20::Erc20Source,
+    erc721::Erc721Source,
+    events::EventSource,
+    governance::GovernanceSource,
+    logs::{default_chunk_size, HolderDiscovery},
+    multicall::DEFAULT_MULTICALL_CHUNK_SIZE,
+    multipliers::TokenMultipliers,
+    pool::PoolSource,
+    staking::{StakedSource, StakingContract},
+    time_weighted::TimeWeightedSource,
+    uniswap_v3::UniswapV3Source,
+    Source,
+};
+use crate::storage::{
+    http::HttpPutStorage, kubo::KuboStorage, pinata::PinataStorage, StorageBackend,
+};
+use anyhow::Result;
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    str::FromStr,
+};
+use wavs_wasi_utils::evm::alloy_primitives::{Address, U256};
+
+/// Default source weight (1x, in basis points).
+pub const DEFAULT_WEIGHT_BPS: u64 = 10_000;
+
+/// Specification of a single reward source, as read from the `reward_sources`
+/// config var.
+///
+/// Example:
+///
+/// ```json
+/// [
+///   { "type": "erc721", "address": "0x...", "rate": "1000000000000000000" },
+///   {
+///     "type": "erc20",
+///     "address": "0x...",
+///     "chain": "local",
+///     "rate": "500000000000000000",
+///     "weight": 5000,
+///     "reward_token": "0x...",
+///     "discovery": { "type": "transfer_logs", "from_block": 0 }
+///   },
+///   {
+///     "type": "erc721",
+///     "address": "0x...",
+///     "rate": "1",
+///     "pool": "10000000000000000000000",
+///     "contract_accounts": {
+///       "mode": "redirect",
+///       "beneficiaries": { "0x<vault>": "0x<owner>" }
+///     },
+///     "staking": [
+///       { "address": "0x...", "lookup": { "type": "staked_by" }, "from_block": 0 }
+///     ]
+///   },
+///   {
+///     "type": "uniswap_v3",
+///     "position_manager": "0x...",
+///     "pool_address": "0x...",
+///     "pool": "5000000000000000000000",
+///     "from_block": 0
+///   },
+///   {
+///     "type": "event",
+///     "address": "0x...",
+///     "event": "Deposit(address,uint256)",
+///     "account_topic": 1,
+///     "rate": "1000000000000000000",
+///     "from_block": 0
+///   }
+/// ]
+/// ```
+#[derive(Clone, Debug, Deserialize)]
+pub struct SourceSpec {
+    /// The source type and its type-specific fields.
+    #[serde(flatten)]
+    pub kind: SourceKind,
+    /// The chain the source lives on, as named in the WAVS chain config.
+    #[serde(default = "default_chain")]
+    pub chain: String,
+    /// Weight applied to the source's rewards, in basis points (10000 = 1x).
+    #[serde(default = "default_weight")]
+    pub weight: u64,
+    /// Token the source pays rewards in, defaulting to the `reward_token`
+    /// config var.
+    #[serde(default)]
+    pub reward_token: Option<String>,
+    /// Fixed rewards per epoch, as a decimal or 0x-prefixed string. When set,
+    /// the pool is split pro-rata to the source's rewards instead of paying
+    /// them directly.
+    #[serde(default)]
+    pub pool: Option<String>,
+    /// How accounts with contract code are treated, checked with
+    /// `eth_getCode` at the snapshot block.
+    #[serde(default)]
+    pub contract_accounts: ContractAccounts,
+    /// Staking contracts whose rewards are credited to the accounts that
+    /// staked NFTs with them.
+    #[serde(default)]
+    pub staking: Vec<StakingContract>,
+}
+
+/// The type-specific part of a [`SourceSpec`].
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SourceKind {
+    /// Flat rewards per NFT held.
+    Erc721 {
+        address: String,
+        /// Rewards per NFT, as a decimal or 0x-prefixed string.
+        rate: String,
+        #[serde(default)]
+        discovery: HolderDiscovery,
+        /// Balance queries batched per Multicall3 call (0 disables batching).
+        #[serde(default = "default_batch_size")]
+        batch_size: usize,
+        /// Per-token-ID multipliers applied to `rate`.
+        #[serde(default)]
+        multipliers: Option<TokenMultipliers>,
+    },
+    /// Rewards proportional to the ERC20 balance held.
+    Erc20 {
+        address: String,
+        /// Rewards per whole token, as a decimal or 0x-prefixed string.
+        rate: String,
+        #[serde(default)]
+        discovery: HolderDiscovery,
+        /// Balance queries batched per Multicall3 call (0 disables batching).
+        #[serde(default = "default_batch_size")]
+        batch_size: usize,
+    },
+    /// Rewards proportional to balance × blocks held within an epoch.
+    TimeWeighted {
+        address: String,
+        /// Rewards per whole token held for one block, as a decimal or
+        /// 0x-prefixed string.
+        rate: String,
+        /// Token decimals (0 for ERC721).
+        #[serde(default)]
+        decimals: u8,
+        /// Block to start replaying `Transfer` logs from, at or before the
+        /// contract deployment.
+        #[serde(default)]
+        from_block: u64,
+        /// First block of the epoch.
+        start_block: u64,
+        /// Last block of the epoch, defaulting to the snapshot block.
+        #[serde(default)]
+        end_block: Option<u64>,
+        #[serde(default = "default_chunk_size")]
+        chunk_size: u64,
+    },
+    /// Rewards per unit of each ERC1155 token ID held.
+    Erc1155 {
+        address: String,
+        /// Rewards per unit held, as decimal or 0x-prefixed strings, keyed by
+        /// token ID. Token IDs without a rate are not rewarded.
+        rates: BTreeMap<String, String>,
+        /// Block to start replaying transfer logs from, at or before the
+        /// contract deployment.
+        #[serde(default)]
+        from_block: u64,
+        #[serde(default = "default_chunk_size")]
+        chunk_size: u64,
+    },
+    /// Rewards proportional to in-range Uniswap V3 liquidity in a pool.
+    UniswapV3 {
+        /// NonfungiblePositionManager address.
+        position_manager: String,
+        /// Pool address. Named apart from the spec's `pool`, the fixed
+        /// rewards per epoch.
+        pool_address: String,
+        /// Rewards per unit of in-range liquidity, as a decimal or 0x-prefixed
+        /// string.
+        #[serde(default = "default_rate")]
+        rate: String,
+        /// Block to start scanning the pool's `Mint` logs from, at or before
+        /// the pool deployment.
+        #[serde(default)]
+        from_block: u64,
+        #[serde(default = "default_chunk_size")]
+        chunk_size: u64,
+        /// Position queries batched per Multicall3 call (0 disables batching).
+        #[serde(default = "default_batch_size")]
+        batch_size: usize,
+    },
+    /// Rewards per vote cast on an OpenZeppelin Governor.
+    Governance {
+        /// Governor address.
+        address: String,
+        /// Rewards per vote, or per whole unit of voting power if
+        /// `weighted`, as a decimal or 0x-prefixed string.
+        rate: String,
+        /// Weight each vote by the voting power cast.
+        #[serde(default)]
+        weighted: bool,
+        /// Voting token decimals, used when `weighted`.
+        #[serde(default = "default_decimals")]
+        decimals: u8,
+        /// First block votes are counted from.
+        from_block: u64,
+        /// Last block votes are counted to, defaulting to the snapshot block.
+        #[serde(default)]
+        to_block: Option<u64>,
+        #[serde(default = "default_chunk_size")]
+        chunk_size: u64,
+    },
+    /// Rewards per occurrence of an arbitrary event, credited to the account
+    /// in one of its indexed topics.
+    Event {
+        /// Contract emitting the event.
+        address: String,
+        /// Canonical event signature, e.g. `Deposit(address,uint256)`.
+        event: String,
+        /// Indexed topic holding the rewarded account (1 to 3).
+        account_topic: usize,
+        /// Rewards per event, or per whole unit of the weight if
+        /// `weight_index` is set, as a decimal or 0x-prefixed string.
+        rate: String,
+        /// Non-indexed data word (counted from 0) to weight each event by.
+        #[serde(default)]
+        weight_index: Option<usize>,
+        /// Decimals of the weight, used with `weight_index`.
+        #[serde(default)]
+        decimals: u8,
+        /// First block events are counted from.
+        from_block: u64,
+        /// Last block events are counted to, defaulting to the snapshot block.
+        #[serde(default)]
+        to_block: Option<u64>,
+        #[serde(default = "default_chunk_size")]
+        chunk_size: u64,
+    },
+}
+
+impl SourceKind {
+    /// The address of the token contract the source reads from.
+    pub fn address(&self) -> &str {
+        match self {
+            SourceKind::Erc721 { address, .. }
+            | SourceKind::Erc20 { address, .. }
+            | SourceKind::TimeWeighted { address, .. }
+            | SourceKind::Erc1155 { address, .. }
+            | SourceKind::Governance { address, .. }
+            | SourceKind::Event { address, .. } => address,
+            SourceKind::UniswapV3 { position_manager, .. } => position_manager,
+        }
+    }
+}
+
+impl SourceSpec {
+    /// Build the source described by this spec, using the provider and
+    /// snapshot block for its chain.
+    pub fn build(&self, provider: ChainProvider, block: u64) -> Result<Box<dyn Source>> {
+        let source: Box<dyn Source> = match &self.kind {
+            SourceKind::Erc721 { address, rate, discovery, batch_size, multipliers } => {
+                let mut source = Erc721Source::new(
+                    &self.chain,
+                    provider.clone(),
+                    block,
+                    address,
+                    parse_u256(rate)?,
+                )?
+                .with_discovery(discovery.clone())
+                .with_batch_size(*batch_size);
+                if let Some(multipliers) = multipliers {
+                    source = source.with_multipliers(multipliers.clone());
+                }
+                Box::new(source)
+            }
+            SourceKind::Erc20 { address, rate, discovery, batch_size } => Box::new(
+                Erc20Source::new(&self.chain, provider.clone(), block, address, parse_u256(rate)?)?
+                    .with_discovery(discovery.clone())
+                    .with_batch_size(*batch_size),
+            ),
+            SourceKind::TimeWeighted {
+                address,
+                rate,
+                decimals,
+                from_block,
+                start_block,
+                end_block,
+                chunk_size,
+            } => Box::new(TimeWeightedSource::new(
+                &self.chain,
+                provider.clone(),
+                address,
+                parse_u256(rate)?,
+                *decimals,
+                *from_block,
+                *start_block,
+                end_block.unwrap_or(block),
+                *chunk_size,
+            )?),
+            SourceKind::Erc1155 { address, rates, from_block, chunk_size } => {
+                let rates = rates
+                    .iter()
+                    .map(|(token_id, rate)| Ok((parse_u256(token_id)?, parse_u256(rate)?)))
+                    .collect::<Result<_>>()?;
+                Box::new(Erc1155Source::new(
+                    &self.chain,
+                    provider.clone(),
+                    block,
+                    address,
+                    rates,
+                    *from_block,
+                    *chunk_size,
+                )?)
+            }
+            SourceKind::UniswapV3 {
+                position_manager,
+                pool_address,
+                rate,
+                from_block,
+                chunk_size,
+                batch_size,
+            } => Box::new(
+                UniswapV3Source::new(
+                    &self.chain,
+                    provider.clone(),
+                    block,
+                    position_manager,
+                    pool_address,
+                    parse_u256(rate)?,
+                    *from_block,
+                )?
+                .with_chunk_size(*chunk_size)
+                .with_batch_size(*batch_size),
+            ),
+            SourceKind::Governance {
+                address,
+                rate,
+                weighted,
+                decimals,
+                from_block,
+                to_block,
+                chunk_size,
+            } => {
+                let mut source = GovernanceSource::new(
+                    &self.chain,
+                    provider.clone(),
+                    address,
+                    parse_u256(rate)?,
+                    *from_block,
+                    to_block.unwrap_or(block),
+                    *chunk_size,
+                )?;
+                if *weighted {
+                    source = source.with_voting_power(*decimals);
+                }
+                Box::new(source)
+            }
+            SourceKind::Event {
+                address,
+                event,
+                account_topic,
+                rate,
+                weight_index,
+                decimals,
+                from_block,
+                to_block,
+                chunk_size,
+            } => {
+                let mut source = EventSource::new(
+                    &self.chain,
+                    provider.clone(),
+                    address,
+                    event,
+                    *account_topic,
+                    parse_u256(rate)?,
+                    *from_block,
+                    to_block.unwrap_or(block),
+                    *chunk_size,
+                )?;
+                if let Some(index) = weight_index {
+                    source = source.with_weight(*index, *decimals);
+                }
+                Box::new(source)
+            }
+        };
+        let source: Box<dyn Source> = if self.staking.is_empty() {
+            source
+        } else {
+            let mut staked = StakedSource::new(
+                source,
+                provider.clone(),
+                block,
+                self.kind.address(),
+                self.staking.clone(),
+            )?;
+            if let SourceKind::Erc721 { multipliers: Some(multipliers), .. } = &self.kind {
+                staked = staked.with_multipliers(multipliers.clone());
+            }
+            Box::new(staked)
+        };
+        let source: Box<dyn Source> = match &self.contract_accounts {
+            ContractAccounts::Keep => source,
+            mode => Box::new(ContractAccountSource::new(source, provider, block, mode.clone())?),
+        };
+        if let Some(pool) = &self.pool {
+            return Ok(Box::new(PoolSource::new(source, parse_u256(pool)?)));
+        }
+        Ok(source)
+    }
+}
+
+/// Load the reward source specs from config.
+///
+/// Reads the `reward_sources` JSON array if set. Otherwise falls back to the
+/// legacy `reward_source_nft` / `reward_source_token` /
+/// `holder_discovery_from_block` vars, paying 1e18 per NFT or whole token.
+pub fn load_source_specs() -> Result<Vec<SourceSpec>> {
+    if let Some(reward_sources) = config_var("reward_sources") {
+        return serde_json::from_str(&reward_sources)
+            .map_err(|e| anyhow::anyhow!("Invalid reward_sources config: {}", e));
+    }
+
+    let discovery = match config_var("holder_discovery_from_block") {
+        Some(from_block) => HolderDiscovery::transfer_logs(
+            from_block.parse().map_err(|e| anyhow::anyhow!("Invalid from block: {}", e))?,
+        ),
+        None => HolderDiscovery::GetAllHolders,
+    };
+    let rate = U256::from(1e18).to_string();
+
+    let mut specs = Vec::new();
+    if let Some(address) = config_var("reward_source_nft") {
+        specs.push(SourceSpec {
+            kind: SourceKind::Erc721 {
+                address,
+                rate: rate.clone(),
+                discovery: discovery.clone(),
+                batch_size: DEFAULT_MULTICALL_CHUNK_SIZE,
+                multipliers: None,
+            },
+            chain: default_chain(),
+            weight: DEFAULT_WEIGHT_BPS,
+            reward_token: None,
+            pool: None,
+            contract_accounts: ContractAccounts::Keep,
+            staking: vec![],
+        });
+    }
+    if let Some(address) = config_var("reward_source_token") {
+        specs.push(SourceSpec {
+            kind: SourceKind::Erc20 {
+                address,
+                rate,
+                discovery,
+                batch_size: DEFAULT_MULTICALL_CHUNK_SIZE,
+            },
+            chain: default_chain(),
+            weight: DEFAULT_WEIGHT_BPS,
+            reward_token: None,
+            pool: None,
+            contract_accounts: ContractAccounts::Keep,
+            staking: vec![],
+        });
+    }
+
+    if specs.is_empty() {
+        return Err(anyhow::anyhow!("No reward sources configured"));
+    }
+    Ok(specs)
+}
+
+/// Load the storage backend the tree is uploaded to.
+///
+/// Selected by the `storage_backend` config var:
+/// - `pinata` (default): uses the `WAVS_ENV_PINATA_API_URL` and
+///   `WAVS_ENV_PINATA_API_KEY` env vars.
+/// - `kubo`: uploads to the Kubo RPC API at `kubo_api_url`.
+/// - `http`: PUTs the file under `storage_put_url`, with the optional
+///   `WAVS_ENV_STORAGE_API_KEY` env var as a bearer token.
+pub fn load_storage_backend() -> Result<Box<dyn StorageBackend>> {
+    let backend = config_var("storage_backend").unwrap_or_else(|| "pinata".to_string());
+    let storage: Box<dyn StorageBackend> = match backend.as_str() {
+        "pinata" => {
+            let api_url = std::env::var("WAVS_ENV_PINATA_API_URL")
+                .unwrap_or_else(|_| "https://uploads.pinata.cloud/v3/files".to_string());
+            let api_key = std::env::var("WAVS_ENV_PINATA_API_KEY")
+                .map_err(|e| anyhow::anyhow!("Failed to get API key: {}", e))?;
+            Box::new(PinataStorage::new(&api_url, &api_key))
+        }
+        "kubo" => {
+            let api_url =
+                config_var("kubo_api_url").unwrap_or_else(|| "http://127.0.0.1:5001".to_string());
+            Box::new(KuboStorage::new(&api_url))
+        }
+        "http" => {
+            let url = config_var("storage_put_url")
+                .ok_or(anyhow::anyhow!("Failed to get storage_put_url"))?;
+            let api_key = std::env::var("WAVS_ENV_STORAGE_API_KEY").ok();
+            Box::new(HttpPutStorage::new(&url, api_key))
+        }
+        _ => return Err(anyhow::anyhow!("Unknown storage backend: {}", backend)),
+    };
+    Ok(storage)
+}
+
+/// Load the retry and rate limiting settings for RPC requests from the
+/// `rpc_max_retries`, `rpc_backoff_ms` and `rpc_requests_per_second` config
+/// vars.
+pub fn load_rpc_config() -> Result<RpcConfig> {
+    let mut rpc_config = RpcConfig::default();
+    if let Some(max_retries) = config_var("rpc_max_retries") {
+        rpc_config.max_retries =
+            max_retries.parse().map_err(|e| anyhow::anyhow!("Invalid rpc_max_retries: {}", e))?;
+    }
+    if let Some(backoff_ms) = config_var("rpc_backoff_ms") {
+        rpc_config.backoff_ms =
+            backoff_ms.parse().map_err(|e| anyhow::anyhow!("Invalid rpc_backoff_ms: {}", e))?;
+    }
+    if let Some(requests_per_second) = config_var("rpc_requests_per_second") {
+        rpc_config.requests_per_second = Some(
+            requests_per_second
+                .parse()
+                .map_err(|e| anyhow::anyhow!("Invalid rpc_requests_per_second: {}", e))?,
+        );
+    }
+    Ok(rpc_config)
+}
+
+/// The `reward_policy` config var, before amounts are parsed.
+#[derive(Deserialize)]
+struct RewardPolicyConfig {
+    #[serde(default)]
+    max_per_account: Option<String>,
+    #[serde(default)]
+    min_per_account: Option<String>,
+    #[serde(default)]
+    below_minimum: BelowMinimum,
+}
+
+/// Load the per-account reward limits from the `reward_policy` config var.
+///
+/// Example:
+///
+/// ```json
+/// {
+///   "max_per_account": "1000000000000000000000",
+///   "min_per_account": "1000000000000000",
+///   "below_minimum": "carry_over"
+/// }
+/// ```
+pub fn load_reward_policy() -> Result<RewardPolicy> {
+    let Some(reward_policy) = config_var("reward_policy") else {
+        return Ok(RewardPolicy::default());
+    };
+    parse_reward_policy(&reward_policy, config_var("reward_distributor").is_some())
+}
+
+/// Parse the `reward_policy` config var. Carrying amounts over needs a
+/// distributor, since they are read back from the tree it points to.
+fn parse_reward_policy(reward_policy: &str, has_distributor: bool) -> Result<RewardPolicy> {
+    let config: RewardPolicyConfig = serde_json::from_str(reward_policy)
+        .map_err(|e| anyhow::anyhow!("Invalid reward_policy config: {}", e))?;
+
+    let policy = RewardPolicy {
+        max_per_account: config.max_per_account.as_deref().map(parse_u256).transpose()?,
+        min_per_account: config.min_per_account.as_deref().map(parse_u256).transpose()?,
+        below_minimum: config.below_minimum,
+    };
+    if let (Some(max), Some(min)) = (policy.max_per_account, policy.min_per_account) {
+        if min > max {
+            return Err(anyhow::anyhow!(
+                "reward_policy min_per_account {} is above max_per_account {}",
+                min,
+                max
+            ));
+        }
+    }
+    if policy.below_minimum == BelowMinimum::CarryOver && !has_distributor {
+        return Err(anyhow::anyhow!(
+            "reward_policy below_minimum carry_over requires reward_distributor"
+        ));
+    }
+    Ok(policy)
+}
+
+/// Load the accounts allowed into the distribution from the
+/// `account_allowlist` and `account_denylist` config vars, each a JSON array
+/// of addresses.
+pub fn load_account_filter() -> Result<AccountFilter> {
+    let allowlist = config_var("account_allowlist")
+        .map(|allowlist| parse_address_list("account_allowlist", &allowlist))
+        .transpose()?;
+    let denylist = config_var("account_denylist")
+        .map(|denylist| parse_address_list("account_denylist", &denylist))
+        .transpose()?
+        .unwrap_or_default();
+    Ok(AccountFilter { allowlist, denylist })
+}
+
+fn parse_address_list(name: &str, value: &str) -> Result<BTreeSet<Address>> {
+    let addresses: Vec<String> = serde_json::from_str(value)
+        .map_err(|e| anyhow::anyhow!("Invalid {} config: {}", name, e))?;
+    addresses
+        .iter()
+        .map(|address| {
+            Address::from_str(address)
+                .map_err(|e| anyhow::anyhow!("Invalid address {} in {}: {}", address, name, e))
+        })
+        .collect()
+}
+
+/// Load the pinned snapshot block for each chain.
+///
+/// Blocks set in the `snapshot_blocks` config var (a JSON object of chain
+/// name to block number) take precedence. Otherwise the block of the
+/// triggering event is used for its chain. Cron-triggered runs resolve the
+/// remaining chains from the trigger time once providers are available, so
+/// `snapshot_blocks` is only needed for other chains of event-triggered runs.
+pub fn load_snapshot_blocks(trigger_block: Option<(String, u64)>) -> Result<BTreeMap<String, u64>> {
+    let mut blocks = BTreeMap::new();
+    if let Some((chain_name, block_height)) = trigger_block {
+        blocks.insert(chain_name, block_height);
+    }
+    if let Some(snapshot_blocks) = config_var("snapshot_blocks") {
+        let configured: BTreeMap<String, u64> = serde_json::from_str(&snapshot_blocks)
+            .map_err(|e| anyhow::anyhow!("Invalid snapshot_blocks config: {}", e))?;
+        blocks.extend(configured);
+    }
+    Ok(blocks)
+}
+
+/// Parse a decimal or 0x-prefixed hex string into a U256.
+pub fn parse_u256(value: &str) -> Result<U256> {
+    U256::from_str(value).map_err(|e| anyhow::anyhow!("Invalid amount {}: {}", value, e))
+}
+
+fn default_chain() -> String {
+    "local".to_string()
+}
+
+fn default_rate() -> String {
+    "1".to_string()
+}
+
+fn default_decimals() -> u8 {
+    18
+}
+
+fn default_weight() -> u64 {
+    DEFAULT_WEIGHT_BPS
+}
+
+fn default_batch_size() -> usize {
+    DEFAULT_MULTICALL_CHUNK_SIZE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uniswap_v3_spec_keeps_pool_address_apart_from_pool() {
+        let spec: SourceSpec = serde_json::from_str(
+            r#"{
+                "type": "uniswap_v3",
+                "chain": "mainnet",
+                "position_manager": "0xC36442b4a4522E871399CD717aBDD847Ab11FE88",
+                "pool_address": "0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640",
+                "rate": "2",
+                "from_block": 12369621,
+                "chunk_size": 5000,
+                "batch_size": 50,
+                "weight": 5000,
+                "reward_token": "0x1111111111111111111111111111111111111111",
+                "pool": "5000000000000000000000"
+            }"#,
+        )
+        .unwrap();
+
+        let SourceKind::UniswapV3 {
+            position_manager,
+            pool_address,
+            rate,
+            from_block,
+            chunk_size,
+            batch_size,
+        } = &spec.kind
+        else {
+            panic!("Expected a uniswap_v3 source, got {:?}", spec.kind);
+        };
+        assert_eq!(position_manager, "0xC36442b4a4522E871399CD717aBDD847Ab11FE88");
+        assert_eq!(pool_address, "0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640");
+        assert_eq!(rate, "2");
+        assert_eq!(*from_block, 12369621);
+        assert_eq!(*chunk_size, 5000);
+        assert_eq!(*batch_size, 50);
+        assert_eq!(spec.chain, "mainnet");
+        assert_eq!(spec.weight, 5000);
+        assert_eq!(
+            spec.reward_token.as_deref(),
+            Some("0x1111111111111111111111111111111111111111")
+        );
+        assert_eq!(spec.pool.as_deref(), Some("5000000000000000000000"));
+    }
+
+    #[test]
+    fn carry_over_requires_a_distributor() {
+        let reward_policy = r#"{ "min_per_account": "100", "below_minimum": "carry_over" }"#;
+        let err = parse_reward_policy(reward_policy, false).unwrap_err();
+        assert!(err.to_string().contains("reward_distributor"), "{}", err);
+
+        let policy = parse_reward_policy(reward_policy, true).unwrap();
+        assert_eq!(policy.below_minimum, BelowMinimum::CarryOver);
+        assert_eq!(policy.min_per_account, Some(U256::from(100)));
+    }
+
+    #[test]
+    fn dropping_below_minimum_needs_no_distributor() {
+        let policy = parse_reward_policy(r#"{ "min_per_account": "100" }"#, false).unwrap();
+        assert_eq!(policy.below_minimum, BelowMinimum::Drop);
+    }
+
+    #[test]
+    fn minimum_above_maximum_is_rejected() {
+        let reward_policy = r#"{ "max_per_account": "10", "min_per_account": "100" }"#;
+        assert!(parse_reward_policy(reward_policy, true).is_err());
+    }
+
+    #[test]
+    fn uniswap_v3_spec_defaults() {
+        let spec: SourceSpec = serde_json::from_str(
+            r#"{
+                "type": "uniswap_v3",
+                "position_manager": "0xC36442b4a4522E871399CD717aBDD847Ab11FE88",
+                "pool_address": "0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640"
+            }"#,
+        )
+        .unwrap();
+
+        let SourceKind::UniswapV3 { rate, from_block, chunk_size, batch_size, .. } = &spec.kind
+        else {
+            panic!("Expected a uniswap_v3 source, got {:?}", spec.kind);
+        };
+        assert_eq!(rate, "1");
+        assert_eq!(*from_block, 0);
+        assert_eq!(*chunk_size, default_chunk_size());
+        assert_eq!(*batch_size, DEFAULT_MULTICALL_CHUNK_SIZE);
+        assert_eq!(spec.pool, None);
+    }
+}
@@ -0,0 +1,161 @@
+use alloy_sol_types::{sol, SolCall};
+use anyhow::Result;
+use std::{collections::BTreeMap, str::FromStr};
+use wavs_wasi_utils::evm::alloy_primitives::{Address, U256};
+
+use crate::{
+    ipfs::fetch_from_ipfs, provider::ChainProvider, sources::multicall::call, MerkleTreeIpfsData,
+};
+
+/// The previously published tree, as referenced by the distributor.
+pub struct PreviousTree {
+    /// The IPFS CID of the previous tree.
+    pub ipfs_hash: String,
+    /// Cumulative claimable amounts, keyed by (account, reward token).
+    pub claimable: BTreeMap<(Address, Address), U256>,
+    /// Amounts kept out of the tree by the reward policy, keyed by (account,
+    /// reward token).
+    pub carried_over: BTreeMap<(Address, Address), U256>,
+    /// Snapshot block per chain the previous tree was computed at. Empty for
+    /// trees that don't record them.
+    pub snapshot_blocks: BTreeMap<String, u64>,
+}
+
+impl PreviousTree {
+    /// The first chain whose block in `snapshot_blocks` is not newer than the
+    /// previous tree's, along with that block. Such a snapshot was already
+    /// counted, so building on the tree again would pay the epoch twice.
+    pub fn stale_snapshot<'a>(
+        &self,
+        snapshot_blocks: &'a BTreeMap<String, u64>,
+    ) -> Option<(&'a str, u64)> {
+        snapshot_blocks.iter().find_map(|(chain_name, block)| {
+            let previous = self.snapshot_blocks.get(chain_name)?;
+            (block <= previous).then_some((chain_name.as_str(), *block))
+        })
+    }
+}
+
+/// Fetch the tree currently set on the distributor, via its `ipfsHashCid`.
+///
+/// Returns `None` if no tree has been published yet.
+pub async fn get_previous_tree(
+    provider: &ChainProvider,
+    distributor: Address,
+    block: u64,
+    ipfs_gateway_url: &str,
+) -> Result<Option<PreviousTree>> {
+    let cid_call = IRewardDistributor::ipfsHashCidCall {};
+    let result = call(provider, block, distributor, cid_call.abi_encode().into()).await?;
+    let ipfs_hash = IRewardDistributor::ipfsHashCidCall::abi_decode_returns(&result)?;
+    if ipfs_hash.is_empty() {
+        return Ok(None);
+    }
+
+    let data = fetch_from_ipfs(&ipfs_hash, ipfs_gateway_url).await?;
+    let previous: MerkleTreeIpfsData = serde_json::from_slice(&data)
+        .map_err(|e| anyhow::anyhow!("Failed to parse previous tree {}: {}", ipfs_hash, e))?;
+
+    let mut claimable = BTreeMap::new();
+    for entry in previous.tree {
+        let account = Address::from_str(&entry.account)?;
+        let reward = Address::from_str(&entry.reward)?;
+        let amount = U256::from_str(&entry.claimable)?;
+        claimable.insert((account, reward), amount);
+    }
+
+    let mut carried_over = BTreeMap::new();
+    for entry in previous.carried_over {
+        let account = Address::from_str(&entry.account)?;
+        let reward = Address::from_str(&entry.reward)?;
+        let amount = U256::from_str(&entry.amount)?;
+        carried_over.insert((account, reward), amount);
+    }
+
+    let snapshot_blocks = match previous.metadata.get("snapshot_blocks") {
+        Some(blocks) => serde_json::from_value(blocks.clone()).map_err(|e| {
+            anyhow::anyhow!("Invalid snapshot blocks in previous tree {}: {}", ipfs_hash, e)
+        })?,
+        None => BTreeMap::new(),
+    };
+
+    Ok(Some(PreviousTree { ipfs_hash, claimable, carried_over, snapshot_blocks }))
+}
+
+/// Add this epoch's rewards, formatted as [account, token, amount][], onto
+/// the previous tree's claimable amounts.
+///
+/// Accounts that only appear in the previous tree keep their claimable amount,
+/// so earned rewards never disappear from the root. The result is sorted by
+/// account, then reward token.
+pub fn add_previous_claimable(
+    values: Vec<Vec<String>>,
+    previous: &PreviousTree,
+) -> Result<Vec<Vec<String>>> {
+    let mut claimable = previous.claimable.clone();
+    for value in values {
+        let account = Address::from_str(&value[0])?;
+        let reward = Address::from_str(&value[1])?;
+        let amount = U256::from_str(&value[2])?;
+        let total = claimable.entry((account, reward)).or_default();
+        *total = total.checked_add(amount).ok_or(anyhow::anyhow!("Cumulative rewards overflow"))?;
+    }
+
+    Ok(claimable
+        .into_iter()
+        .map(|((account, reward), amount)| {
+            vec![account.to_string(), reward.to_string(), amount.to_string()]
+        })
+        .collect())
+}
+
+sol! {
+    interface IRewardDistributor {
+        function ipfsHashCid() external view returns (string memory);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn previous(snapshot_blocks: &[(&str, u64)]) -> PreviousTree {
+        PreviousTree {
+            ipfs_hash: String::new(),
+            claimable: BTreeMap::new(),
+            carried_over: BTreeMap::new(),
+            snapshot_blocks: snapshot_blocks
+                .iter()
+                .map(|(chain_name, block)| (chain_name.to_string(), *block))
+                .collect(),
+        }
+    }
+
+    fn blocks(snapshot_blocks: &[(&str, u64)]) -> BTreeMap<String, u64> {
+        previous(snapshot_blocks).snapshot_blocks
+    }
+
+    #[test]
+    fn newer_snapshot_is_not_stale() {
+        let previous = previous(&[("local", 100), ("base", 50)]);
+        assert_eq!(previous.stale_snapshot(&blocks(&[("local", 101), ("base", 51)])), None);
+        // Chains the previous tree didn't snapshot can't have been counted.
+        assert_eq!(previous.stale_snapshot(&blocks(&[("local", 101), ("op", 1)])), None);
+    }
+
+    #[test]
+    fn same_or_older_snapshot_is_stale() {
+        let previous = previous(&[("local", 100), ("base", 50)]);
+        assert_eq!(previous.stale_snapshot(&blocks(&[("local", 100)])), Some(("local", 100)));
+        assert_eq!(previous.stale_snapshot(&blocks(&[("local", 99)])), Some(("local", 99)));
+        assert_eq!(
+            previous.stale_snapshot(&blocks(&[("local", 101), ("base", 50)])),
+            Some(("base", 50))
+        );
+    }
+
+    #[test]
+    fn trees_without_snapshot_blocks_are_never_stale() {
+        assert_eq!(previous(&[]).stale_snapshot(&blocks(&[("local", 1)])), None);
+    }
+}
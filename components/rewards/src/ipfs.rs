@@ -1,145 +1,179 @@
 use anyhow::Result;
-use serde::Deserialize;
-use std::{
-    fs::File,
-    io::{Read, Write},
-};
-use wstd::http::{IntoBody, Request};
+use wstd::http::Request;
 use wstd::io::AsyncRead;
 
+use cid::multihash::{Code, MultihashDigest};
 use cid::Cid;
 use std::str::FromStr;
 
-/// Uploads a file using multipart request to IPFS
-async fn upload_to_ipfs(file_path: &str, name: &str, ipfs_url: &str, api_key: &str) -> Result<Cid> {
-    eprintln!("Uploading file to IPFS: {}", file_path);
-
-    let mut file = File::open(file_path)?;
-    let mut file_bytes = Vec::new();
-    file.read_to_end(&mut file_bytes)?;
-
-    // define multipart request boundary
-    let boundary = "----RustBoundary";
-    // construct the body
-    let body = format!(
-        "--{}\r\n\
-        Content-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\n\
-        Content-Type: application/octet-stream\r\n\r\n",
-        boundary, name
-    );
-
-    let mut request_body = body.into_bytes();
-    request_body.extend_from_slice(&file_bytes);
-    request_body.extend_from_slice(format!("\r\n--{}\r\n", boundary).as_bytes());
-
-    // Add network parameter
-    let network_part = format!(
-        "Content-Disposition: form-data; name=\"network\"\r\n\r\n\
-        public\r\n\
-        --{}--\r\n",
-        boundary
-    );
-    request_body.extend_from_slice(network_part.as_bytes());
-
-    let request = Request::post(ipfs_url)
-        .header("Authorization", &format!("Bearer {}", api_key))
-        .header("Content-Type", &format!("multipart/form-data; boundary={}", boundary))
-        .body(request_body.into_body())?;
+/// Multicodec code for raw binary data.
+const RAW_CODEC: u64 = 0x55;
 
-    let mut response = wstd::http::Client::new().send(request).await?;
+/// Multicodec code for dag-pb (UnixFS) nodes.
+const DAG_PB_CODEC: u64 = 0x70;
 
-    if response.status().is_success() {
-        let mut body_buf = Vec::new();
-        response.body_mut().read_to_end(&mut body_buf).await?;
-
-        // Log the raw response for debugging
-        let response_str = std::str::from_utf8(&body_buf)
-            .map_err(|e| anyhow::anyhow!("Failed to convert response to string: {}", e))?;
-        eprintln!("IPFS API Response: {}", response_str);
-
-        // Parse using Pinata's response format (capitalized fields)
-        #[derive(Debug, Deserialize)]
-        struct PinataResponse {
-            data: PinataData,
-        }
+/// Size of the leaves files are split into, as with Kubo's default
+/// `size-262144` chunker.
+const CHUNK_SIZE: usize = 262_144;
 
-        #[derive(Debug, Deserialize)]
-        struct PinataData {
-            cid: String,
-        }
+/// Maximum links per node in Kubo's balanced DAG layout.
+const MAX_LINKS: usize = 174;
 
-        let hash = match serde_json::from_slice::<PinataResponse>(&body_buf) {
-            Ok(resp) => resp.data.cid,
-            Err(_) => {
-                return Err(anyhow::anyhow!(
-                    "Could not extract hash from response: {}",
-                    response_str
-                ));
-            }
-        };
-
-        // Return the hash directly
-        decode_ipfs_cid(&hash).map_err(|e| anyhow::anyhow!("Failed to decode IPFS CID: {}", e))
+pub fn decode_ipfs_cid(cid_str: &str) -> Result<Cid, String> {
+    // Check if the string is a v0 CID (starts with "Qm" and has length 46).
+    if cid_str.starts_with("Qm") && cid_str.len() == 46 {
+        // Decode as base58
+        let decoded = bs58::decode(cid_str).into_vec().map_err(|e| e.to_string())?;
+        // Attempt to construct a Cid from the decoded bytes
+        let cid = Cid::try_from(decoded).map_err(|e| e.to_string())?;
+        Ok(cid)
     } else {
-        let mut body_buf = Vec::new();
-        response.body_mut().read_to_end(&mut body_buf).await?;
-        let error_body = std::str::from_utf8(&body_buf).unwrap_or("unable to read error body");
-        Err(anyhow::anyhow!(
-            "Failed to upload to IPFS. Status: {:?}, Body: {}",
-            response.status(),
-            error_body
-        ))
+        // Attempt to construct a Cid from the decoded bytes
+        let cid = Cid::from_str(cid_str).map_err(|e| e.to_string())?;
+        Ok(cid)
     }
 }
 
-/// Uploads JSON data directly to IPFS and returns the CID
-pub async fn upload_json_to_ipfs(
-    json_data: &str,
-    name: &str,
-    ipfs_url: &str,
-    api_key: &str,
-) -> Result<Cid> {
-    // Create a temporary file to store the JSON data
-    let temp_path = "/tmp/ipfs_data.json";
+/// Compute the CIDv1 (sha2-256) of a file's contents, as added to IPFS with
+/// Kubo's defaults for CIDv1: raw leaves, the `size-262144` chunker and the
+/// balanced layout. Pinning services build the same DAG.
+///
+/// A file that fits in one chunk is a single raw leaf. Larger files are split
+/// into raw leaves linked by UnixFS dag-pb nodes of up to 174 links each.
+pub fn compute_cid(data: &[u8]) -> Cid {
+    if data.len() <= CHUNK_SIZE {
+        return Cid::new_v1(RAW_CODEC, Code::Sha2_256.digest(data));
+    }
 
-    eprintln!("Temp path {}", temp_path);
+    let mut nodes = data
+        .chunks(CHUNK_SIZE)
+        .map(|chunk| DagNode {
+            cid: Cid::new_v1(RAW_CODEC, Code::Sha2_256.digest(chunk)),
+            file_size: chunk.len() as u64,
+            tree_size: chunk.len() as u64,
+        })
+        .collect::<Vec<_>>();
+    while nodes.len() > 1 {
+        nodes = nodes.chunks(MAX_LINKS).map(DagNode::link).collect();
+    }
+    nodes[0].cid
+}
 
-    // Ensure the /tmp directory exists
-    std::fs::create_dir_all("/tmp")
-        .map_err(|e| anyhow::anyhow!("Failed to create /tmp directory: {}", e))?;
+/// A node of a file's UnixFS DAG.
+struct DagNode {
+    cid: Cid,
+    /// Bytes of file data under the node.
+    file_size: u64,
+    /// Encoded size of the node and everything under it.
+    tree_size: u64,
+}
 
-    // Write JSON to temporary file
-    let mut file = File::create(temp_path)?;
-    file.write_all(json_data.as_bytes())?;
+impl DagNode {
+    /// Build the dag-pb node linking `children`, in order.
+    fn link(children: &[DagNode]) -> DagNode {
+        let file_size = children.iter().map(|child| child.file_size).sum();
+
+        // UnixFS Data: Type = File, filesize, then each child's blocksize.
+        let mut unixfs = Vec::new();
+        put_varint_field(&mut unixfs, 1, 2);
+        put_varint_field(&mut unixfs, 3, file_size);
+        for child in children {
+            put_varint_field(&mut unixfs, 4, child.file_size);
+        }
 
-    // Upload the file
-    let hash = upload_to_ipfs(temp_path, name, ipfs_url, api_key).await?;
+        // PBNode: Links (Hash, Name, Tsize) are encoded before Data.
+        let mut node = Vec::new();
+        for child in children {
+            let mut link = Vec::new();
+            put_bytes_field(&mut link, 1, &child.cid.to_bytes());
+            put_bytes_field(&mut link, 2, &[]);
+            put_varint_field(&mut link, 3, child.tree_size);
+            put_bytes_field(&mut node, 2, &link);
+        }
+        put_bytes_field(&mut node, 1, &unixfs);
 
-    // Clean up the temporary file
-    delete_file(temp_path)?;
+        DagNode {
+            cid: Cid::new_v1(DAG_PB_CODEC, Code::Sha2_256.digest(&node)),
+            file_size,
+            tree_size: node.len() as u64
+                + children.iter().map(|child| child.tree_size).sum::<u64>(),
+        }
+    }
+}
 
-    // Return the IPFS URI
-    Ok(hash)
+fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
 }
 
-/// Delete a file from the filesystem
-pub fn delete_file(file_path: &str) -> Result<()> {
-    std::fs::remove_file(file_path)?;
-    println!("File deleted successfully: {}", file_path);
-    Ok(())
+/// Append a protobuf varint field.
+fn put_varint_field(buf: &mut Vec<u8>, field: u64, value: u64) {
+    put_varint(buf, field << 3);
+    put_varint(buf, value);
 }
 
-pub fn decode_ipfs_cid(cid_str: &str) -> Result<Cid, String> {
-    // Check if the string is a v0 CID (starts with "Qm" and has length 46).
-    if cid_str.starts_with("Qm") && cid_str.len() == 46 {
-        // Decode as base58
-        let decoded = bs58::decode(cid_str).into_vec().map_err(|e| e.to_string())?;
-        // Attempt to construct a Cid from the decoded bytes
-        let cid = Cid::try_from(decoded).map_err(|e| e.to_string())?;
-        Ok(cid)
+/// Append a protobuf length-delimited field.
+fn put_bytes_field(buf: &mut Vec<u8>, field: u64, value: &[u8]) {
+    put_varint(buf, (field << 3) | 2);
+    put_varint(buf, value.len() as u64);
+    buf.extend_from_slice(value);
+}
+
+/// Fetch a file from IPFS through an HTTP gateway
+pub async fn fetch_from_ipfs(cid: &str, gateway_url: &str) -> Result<Vec<u8>> {
+    let url = format!("{}/{}", gateway_url.trim_end_matches('/'), cid);
+    eprintln!("Fetching file from IPFS: {}", url);
+
+    let request = Request::get(&url).body(wstd::io::empty())?;
+    let mut response = wstd::http::Client::new().send(request).await?;
+
+    let mut body_buf = Vec::new();
+    response.body_mut().read_to_end(&mut body_buf).await?;
+
+    if response.status().is_success() {
+        Ok(body_buf)
     } else {
-        // Attempt to construct a Cid from the decoded bytes
-        let cid = Cid::from_str(cid_str).map_err(|e| e.to_string())?;
-        Ok(cid)
+        let error_body = std::str::from_utf8(&body_buf).unwrap_or("unable to read error body");
+        Err(anyhow::anyhow!(
+            "Failed to fetch from IPFS. Status: {:?}, Body: {}",
+            response.status(),
+            error_body
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn small_file_is_a_single_raw_leaf() {
+        assert_eq!(
+            compute_cid(b"hello world").to_string(),
+            "bafkreifzjut3te2nhyekklss27nh3k72ysco7y32koao5eei66wof36n5e"
+        );
+    }
+
+    #[test]
+    fn large_file_is_a_unixfs_dag() {
+        // Four leaves under one dag-pb root.
+        let data = (0..1_000_000u32).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        assert_eq!(
+            compute_cid(&data).to_string(),
+            "bafybeibx62obrkybp46hx3ivh53q4rnptgkunpgtwiib5lfelfgt2ekihm"
+        );
+    }
+
+    #[test]
+    fn file_over_max_links_adds_a_layer() {
+        // One leaf more than a single node can link.
+        let data = vec![0u8; MAX_LINKS * CHUNK_SIZE + 1];
+        assert_eq!(
+            compute_cid(&data).to_string(),
+            "bafybeihqwzd3o6q6v3pmwhzjy22vokhr767burokmqemg63hptx2nqd7ym"
+        );
     }
 }
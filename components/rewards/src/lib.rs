@@ -1,19 +1,28 @@
 pub mod bindings;
+mod config;
+mod cumulative;
 mod ipfs;
 mod merkle;
+mod policy;
+mod provider;
 mod sources;
+mod storage;
 mod trigger;
 
 use crate::bindings::{export, host::config_var, Guest, TriggerAction};
-use crate::sources::SourceRegistry;
+use crate::provider::ProviderCache;
+use crate::sources::{SourceRegistry, SourceReward, TokenRewards};
 use bindings::WasmResponse;
 use merkle::get_merkle_tree;
 use merkle_tree_rs::standard::LeafType;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    str::FromStr,
+};
 use trigger::{decode_trigger_event, encode_trigger_output};
-use wavs_wasi_utils::evm::alloy_primitives::{hex, U256, U512};
-use wit_bindgen_rt::async_support::futures;
+use wavs_wasi_utils::evm::alloy_primitives::{hex, Address, U512};
 use wstd::runtime::block_on;
 
 struct Component;
@@ -21,60 +30,135 @@ export!(Component with_types_in bindings);
 
 impl Guest for Component {
     fn run(action: TriggerAction) -> std::result::Result<Option<WasmResponse>, String> {
+        // The default reward token, for sources that don't set their own.
         let reward_token_address =
             config_var("reward_token").ok_or_else(|| "Failed to get reward token address")?;
-        let reward_source_nft_address =
-            config_var("reward_source_nft").ok_or_else(|| "Failed to get NFT address")?;
-        let ipfs_url = std::env::var("WAVS_ENV_PINATA_API_URL")
-            .unwrap_or_else(|_| "https://uploads.pinata.cloud/v3/files".to_string());
-        let ipfs_api_key = std::env::var("WAVS_ENV_PINATA_API_KEY")
-            .map_err(|e| format!("Failed to get API key: {}", e))?;
-
-        let trigger_id = decode_trigger_event(action.data).map_err(|e| e.to_string())?;
-
-        let mut registry = SourceRegistry::new();
-        // Provide 1e18 rewards per NFT held.
-        registry.add_source(sources::erc721::Erc721Source::new(
-            &reward_source_nft_address,
-            U256::from(1e18),
-        ));
+        let reward_token_address = Address::from_str(&reward_token_address)
+            .map_err(|e| format!("Invalid reward token address: {}", e))?;
+        let storage = config::load_storage_backend().map_err(|e| e.to_string())?;
+
+        let trigger = decode_trigger_event(action.data).map_err(|e| e.to_string())?;
+        let trigger_id = trigger.id;
+
+        // Cumulative rewards are enabled when the distributor address is configured.
+        let distributor = config_var("reward_distributor")
+            .map(|address| Address::from_str(&address))
+            .transpose()
+            .map_err(|e| format!("Invalid reward distributor address: {}", e))?;
+        let distributor_chain = config_var("reward_distributor_chain")
+            .or_else(|| trigger.block.as_ref().map(|(chain_name, _)| chain_name.clone()))
+            .unwrap_or_else(|| "local".to_string());
+        let ipfs_gateway_url = config_var("ipfs_gateway_url")
+            .unwrap_or_else(|| "https://gateway.pinata.cloud/ipfs".to_string());
+
+        let specs = config::load_source_specs().map_err(|e| e.to_string())?;
+        let rpc_config = config::load_rpc_config().map_err(|e| e.to_string())?;
+        let trigger_timestamp = trigger.timestamp;
+        let mut snapshot_blocks =
+            config::load_snapshot_blocks(trigger.block).map_err(|e| e.to_string())?;
+        let reward_policy = config::load_reward_policy().map_err(|e| e.to_string())?;
+        let account_filter = config::load_account_filter().map_err(|e| e.to_string())?;
 
         block_on(async move {
+            let providers = ProviderCache::new(rpc_config);
+
+            // Cron triggers carry no block, so each chain without a configured
+            // block is snapshotted at the last block before the trigger time.
+            if let Some(timestamp) = trigger_timestamp {
+                let chains = specs
+                    .iter()
+                    .map(|spec| spec.chain.clone())
+                    .chain(distributor.map(|_| distributor_chain.clone()))
+                    .filter(|chain_name| !snapshot_blocks.contains_key(chain_name))
+                    .collect();
+                let blocks = providers
+                    .get_blocks_at_timestamp(&chains, timestamp)
+                    .await
+                    .map_err(|e| format!("Failed to get snapshot blocks: {}", e))?;
+                snapshot_blocks.extend(blocks);
+            }
+
+            let registry = SourceRegistry::from_specs(
+                &specs,
+                &providers,
+                snapshot_blocks,
+                reward_token_address,
+            )
+            .await
+            .map_err(|e| e.to_string())?
+            .with_policy(reward_policy)
+            .with_filter(account_filter);
+
+            // Cumulative rewards and carried over amounts build on the tree
+            // currently set on the distributor.
+            let mut previous = None;
+            if let Some(distributor) = distributor {
+                let provider = providers.get(&distributor_chain).map_err(|e| e.to_string())?;
+                let block =
+                    *registry.get_snapshot_blocks().get(&distributor_chain).ok_or(format!(
+                        "No snapshot block for distributor chain {}; set it in the \
+                        snapshot_blocks config",
+                        distributor_chain
+                    ))?;
+                previous =
+                    cumulative::get_previous_tree(&provider, distributor, block, &ipfs_gateway_url)
+                        .await
+                        .map_err(|e| format!("Failed to get previous tree: {}", e))?;
+            }
+
+            // A retried trigger, or cron and event triggers firing together,
+            // must not add the same epoch onto the cumulative amounts twice.
+            if let Some((chain_name, block)) = previous
+                .as_ref()
+                .and_then(|previous| previous.stale_snapshot(registry.get_snapshot_blocks()))
+            {
+                eprintln!(
+                    "Snapshot block {} on {} is not newer than the previous tree's; skipping",
+                    block, chain_name
+                );
+                return Ok(None);
+            }
+
             let accounts = registry.get_accounts().await.map_err(|e| e.to_string())?;
 
-            // each value is [address, token, amount]
-            let values = accounts
-                .into_iter()
-                .map(|account| {
-                    let registry = &registry;
-                    let reward_token_address = reward_token_address.clone();
-                    async move {
-                        let amount =
-                            registry.get_rewards(&account).await.map_err(|e| e.to_string())?;
-                        Ok::<Vec<String>, String>(vec![
-                            account,
-                            reward_token_address,
-                            amount.to_string(),
-                        ])
-                    }
-                })
-                .collect::<Vec<_>>();
+            let mut rewards =
+                registry.get_rewards_for_accounts(&accounts).await.map_err(|e| e.to_string())?;
+            let carried_over = registry
+                .apply_policy(
+                    &mut rewards,
+                    &previous.as_ref().map(|p| p.carried_over.clone()).unwrap_or_default(),
+                )
+                .map_err(|e| e.to_string())?;
 
-            let results = futures::future::join_all(values)
-                .await
-                .into_iter()
-                .collect::<Result<Vec<_>, _>>()?;
+            let (mut results, mut breakdowns) = epoch_values(&rewards);
 
-            let total_rewards =
-                results.iter().map(|v| v[2].parse::<U512>().unwrap()).sum::<U512>().to_string();
+            let epoch_rewards = totals_per_token(&results);
+
+            // Carry the previous tree forward, since the distributor expects
+            // cumulative claimable amounts.
+            let mut previous_ipfs_hash = None;
+            if let Some(previous) = previous {
+                results = cumulative::add_previous_claimable(results, &previous)
+                    .map_err(|e| e.to_string())?;
+                previous_ipfs_hash = Some(previous.ipfs_hash);
+            }
 
-            if results.len() == 0 {
+            let total_rewards = totals_per_token(&results);
+
+            if results.is_empty() && carried_over.is_empty() {
                 eprintln!("No accounts to distribute rewards to");
                 return Ok(None);
             }
 
-            let tree = get_merkle_tree(results.clone())?;
-            let root = tree.root();
+            // When every account was carried over there are no leaves, but the
+            // tree is still published, under a zero root, to keep the carried
+            // amounts for the next epoch.
+            let tree =
+                if results.is_empty() { None } else { Some(get_merkle_tree(results.clone())?) };
+            let root = match &tree {
+                Some(tree) => tree.root(),
+                None => format!("0x{}", "00".repeat(32)),
+            };
             let root_bytes = hex::decode(&root).map_err(|e| e.to_string())?;
 
             let sources_with_metadata =
@@ -83,36 +167,67 @@ impl Guest for Component {
             let mut ipfs_data = MerkleTreeIpfsData {
                 id: root.clone(),
                 metadata: json!({
-                    "num_accounts": results.len(),
-                    "reward_token_address": reward_token_address,
+                    "num_accounts": results.iter().map(|v| &v[0]).collect::<BTreeSet<_>>().len(),
+                    "num_leaves": results.len(),
+                    "reward_token_address": reward_token_address.to_string(),
                     "total_rewards": total_rewards,
+                    "epoch_rewards": epoch_rewards,
+                    "previous_ipfs_hash": previous_ipfs_hash,
                     "sources": sources_with_metadata,
+                    "reward_policy": registry.get_policy().get_metadata(),
+                    "account_filter": registry.get_filter().get_metadata(),
+                    "snapshot_blocks": registry.get_snapshot_blocks(),
                 }),
                 root: root.clone(),
                 tree: vec![],
+                carried_over: carried_over
+                    .into_iter()
+                    .map(|((account, reward), amount)| CarriedOverEntry {
+                        account: account.to_string(),
+                        reward: reward.to_string(),
+                        amount: amount.to_string(),
+                    })
+                    .collect(),
             };
 
             // get proof for each value
-            results.into_iter().for_each(|value| {
-                let proof = tree.get_proof(LeafType::LeafBytes(value.clone()));
-                ipfs_data.tree.push(MerkleTreeEntry {
-                    account: value[0].clone(),
-                    reward: value[1].clone(),
-                    claimable: value[2].clone(),
-                    proof,
+            if let Some(tree) = &tree {
+                results.into_iter().for_each(|value| {
+                    let proof = tree.get_proof(LeafType::LeafBytes(value.clone()));
+                    let breakdown = breakdowns
+                        .remove(&(value[0].clone(), value[1].clone()))
+                        .unwrap_or_default();
+                    ipfs_data.tree.push(MerkleTreeEntry {
+                        account: value[0].clone(),
+                        reward: value[1].clone(),
+                        claimable: value[2].clone(),
+                        proof,
+                        breakdown,
+                    });
                 });
-            });
+            }
 
+            // Entries are sorted by account and metadata keys are sorted by
+            // serde_json's default map, so identical input gives identical bytes.
             let ipfs_data_json = serde_json::to_string(&ipfs_data).map_err(|e| e.to_string())?;
 
-            let cid = ipfs::upload_json_to_ipfs(
-                &ipfs_data_json,
-                &format!("rewards_{}.json", ipfs_data.root),
-                &ipfs_url,
-                &ipfs_api_key,
-            )
-            .await
-            .map_err(|e| format!("Failed to upload IPFS: {}", e))?;
+            // Compute the CID ourselves so a faulty storage backend cannot
+            // change the data hash committed onchain.
+            let expected_cid = ipfs::compute_cid(ipfs_data_json.as_bytes());
+
+            let cid = storage
+                .upload(ipfs_data_json.as_bytes(), &format!("rewards_{}.json", ipfs_data.root))
+                .await
+                .map_err(|e| format!("Failed to upload to {}: {}", storage.get_name(), e))?;
+
+            if cid != expected_cid {
+                return Err(format!(
+                    "CID mismatch from {}: expected {}, got {}",
+                    storage.get_name(),
+                    expected_cid,
+                    cid
+                ));
+            }
 
             let ipfs_hash = cid.hash().digest();
 
@@ -130,6 +245,44 @@ impl Guest for Component {
     }
 }
 
+/// Per-source breakdown of each tree value, keyed by (account, token).
+type Breakdowns = HashMap<(String, String), Vec<SourceReward>>;
+
+/// This epoch's tree values, each `[account, token, amount]` with one value
+/// per reward token the account earned, along with each value's per-source
+/// breakdown keyed by (account, token). Zero totals are left out.
+fn epoch_values(
+    rewards: &BTreeMap<Address, BTreeMap<Address, TokenRewards>>,
+) -> (Vec<Vec<String>>, Breakdowns) {
+    let mut values = vec![];
+    let mut breakdowns = HashMap::new();
+    for (account, tokens) in rewards {
+        for (token, token_rewards) in tokens {
+            if token_rewards.total.is_zero() {
+                continue;
+            }
+            values.push(vec![
+                account.to_string(),
+                token.to_string(),
+                token_rewards.total.to_string(),
+            ]);
+            breakdowns
+                .insert((account.to_string(), token.to_string()), token_rewards.sources.clone());
+        }
+    }
+    (values, breakdowns)
+}
+
+/// Sum the amounts of `[account, token, amount]` values per reward token, as
+/// big number strings. Amounts in different tokens are never added together.
+fn totals_per_token(results: &[Vec<String>]) -> BTreeMap<String, String> {
+    let mut totals = BTreeMap::<String, U512>::new();
+    for value in results {
+        *totals.entry(value[1].clone()).or_default() += value[2].parse::<U512>().unwrap();
+    }
+    totals.into_iter().map(|(token, total)| (token, total.to_string())).collect()
+}
+
 pub mod solidity {
     use alloy_sol_macro::sol;
     pub use ITypes::*;
@@ -137,20 +290,36 @@ pub mod solidity {
     sol!("../../src/interfaces/ITypes.sol");
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 struct MerkleTreeIpfsData {
     id: String,
     metadata: serde_json::Value,
     root: String,
     tree: Vec<MerkleTreeEntry>,
+    /// Rewards kept out of the tree by the reward policy, to be added to a
+    /// later epoch.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    carried_over: Vec<CarriedOverEntry>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 struct MerkleTreeEntry {
     account: String,
     reward: String,
     claimable: String,
     proof: Vec<String>,
+    /// Amount each source added this epoch, with the source's position in the
+    /// registry as a number in `index`. Empty for accounts only carried over
+    /// from the previous tree.
+    #[serde(default)]
+    breakdown: Vec<SourceReward>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CarriedOverEntry {
+    account: String,
+    reward: String,
+    amount: String,
 }
 
 // {
@@ -164,7 +333,80 @@ struct MerkleTreeEntry {
 //         "account": "The address of the claimer",
 //         "reward": "The address of the reward token",
 //         "claimable": "The claimable amount as a big number string",
-//         "proof": ["0x1...", "0x2...", "...", "0xN..."]
+//         "proof": ["0x1...", "0x2...", "...", "0xN..."],
+//         "breakdown": [
+//           {
+//             "source": "The name of the source",
+//             "index": 0,
+//             "amount": "The amount the source added this epoch as a big number string"
+//           }
+//         ]
+//       }
+//     ],
+//     "carried_over": [
+//       {
+//         "account": "The address of the claimer",
+//         "reward": "The address of the reward token",
+//         "amount": "The amount held back for a later epoch as a big number string"
 //       }
 //     ]
 //   }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wavs_wasi_utils::evm::alloy_primitives::U256;
+
+    const A: Address = Address::repeat_byte(0xaa);
+    const B: Address = Address::repeat_byte(0xbb);
+    const TOKEN: Address = Address::repeat_byte(0x11);
+    const OTHER_TOKEN: Address = Address::repeat_byte(0x22);
+
+    fn token_rewards(total: u64, sources: &[(usize, u64)]) -> TokenRewards {
+        TokenRewards {
+            total: U256::from(total),
+            sources: sources
+                .iter()
+                .map(|(index, amount)| SourceReward {
+                    source: "ERC721".to_string(),
+                    index: *index,
+                    amount: amount.to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn epoch_values_skip_zero_totals() {
+        let rewards = BTreeMap::from([
+            (
+                A,
+                BTreeMap::from([
+                    (TOKEN, token_rewards(30, &[(0, 10), (1, 20)])),
+                    (OTHER_TOKEN, token_rewards(0, &[(2, 5)])),
+                ]),
+            ),
+            (B, BTreeMap::from([(OTHER_TOKEN, token_rewards(7, &[(2, 7)]))])),
+        ]);
+
+        let (values, breakdowns) = epoch_values(&rewards);
+        assert_eq!(
+            values,
+            vec![
+                vec![A.to_string(), TOKEN.to_string(), "30".to_string()],
+                vec![B.to_string(), OTHER_TOKEN.to_string(), "7".to_string()],
+            ]
+        );
+
+        assert_eq!(breakdowns.len(), 2);
+        let breakdown = &breakdowns[&(A.to_string(), TOKEN.to_string())];
+        assert_eq!(
+            breakdown
+                .iter()
+                .map(|reward| (reward.index, reward.amount.as_str()))
+                .collect::<Vec<_>>(),
+            vec![(0, "10"), (1, "20")]
+        );
+        assert!(!breakdowns.contains_key(&(A.to_string(), OTHER_TOKEN.to_string())));
+    }
+}
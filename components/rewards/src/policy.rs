@@ -0,0 +1,206 @@
+use anyhow::Result;
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use wavs_wasi_utils::evm::alloy_primitives::{Address, U256};
+
+/// What happens to an account's rewards when they are below the minimum.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BelowMinimum {
+    /// Drop the rewards.
+    #[default]
+    Drop,
+    /// Keep the rewards out of the tree and add them to the account's rewards
+    /// in the next epoch. Requires `reward_distributor`, checked when the
+    /// policy is loaded, since the carried amounts are read back from the
+    /// previous tree.
+    CarryOver,
+}
+
+/// Per-account limits applied to each reward token's totals, after rewards
+/// from all sources are aggregated.
+#[derive(Clone, Debug, Default)]
+pub struct RewardPolicy {
+    /// Maximum an account can earn per epoch. Anything above it is forfeited.
+    pub max_per_account: Option<U256>,
+    /// Minimum an account must earn to be included in the tree.
+    pub min_per_account: Option<U256>,
+    /// What happens to rewards below `min_per_account`.
+    pub below_minimum: BelowMinimum,
+}
+
+impl RewardPolicy {
+    /// Apply the policy to an account's `total` in one token, plus the amount
+    /// `carried` over from earlier epochs.
+    ///
+    /// Returns the amount paid this epoch and the amount carried to the next.
+    pub fn apply(&self, total: U256, carried: U256) -> Result<(U256, U256)> {
+        let mut amount =
+            total.checked_add(carried).ok_or(anyhow::anyhow!("Carried rewards overflow"))?;
+        if let Some(max) = self.max_per_account {
+            amount = amount.min(max);
+        }
+
+        match self.min_per_account {
+            Some(min) if !amount.is_zero() && amount < min => match self.below_minimum {
+                BelowMinimum::Drop => Ok((U256::ZERO, U256::ZERO)),
+                BelowMinimum::CarryOver => Ok((U256::ZERO, amount)),
+            },
+            _ => Ok((amount, U256::ZERO)),
+        }
+    }
+
+    /// Get metadata about the policy.
+    pub fn get_metadata(&self) -> serde_json::Value {
+        serde_json::json!({
+            "max_per_account": self.max_per_account.map(|max| max.to_string()),
+            "min_per_account": self.min_per_account.map(|min| min.to_string()),
+            "below_minimum": match self.below_minimum {
+                BelowMinimum::Drop => "drop",
+                BelowMinimum::CarryOver => "carry_over",
+            },
+        })
+    }
+}
+
+/// Accounts allowed into the distribution. The zero address is always
+/// excluded, since it can never claim.
+#[derive(Clone, Debug, Default)]
+pub struct AccountFilter {
+    /// If set, only these accounts are rewarded.
+    pub allowlist: Option<BTreeSet<Address>>,
+    /// Accounts that are never rewarded, e.g. exchange hot wallets or the
+    /// source contracts themselves.
+    pub denylist: BTreeSet<Address>,
+}
+
+impl AccountFilter {
+    /// Whether `account` may receive rewards.
+    pub fn allows(&self, account: &Address) -> bool {
+        if account.is_zero() || self.denylist.contains(account) {
+            return false;
+        }
+        match &self.allowlist {
+            Some(allowlist) => allowlist.contains(account),
+            None => true,
+        }
+    }
+
+    /// Get metadata about the filter.
+    pub fn get_metadata(&self) -> serde_json::Value {
+        serde_json::json!({
+            "allowlist": self.allowlist.as_ref().map(|allowlist| {
+                allowlist.iter().map(|account| account.to_string()).collect::<Vec<_>>()
+            }),
+            "denylist": self.denylist.iter().map(|account| account.to_string()).collect::<Vec<_>>(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cumulative::{add_previous_claimable, PreviousTree};
+    use crate::sources::{SourceRegistry, TokenRewards};
+    use std::{collections::BTreeMap, str::FromStr};
+
+    const A: Address = Address::repeat_byte(0xaa);
+    const B: Address = Address::repeat_byte(0xbb);
+    const C: Address = Address::repeat_byte(0xcc);
+    const TOKEN: Address = Address::repeat_byte(0x11);
+
+    fn policy(max: Option<u64>, min: Option<u64>, below_minimum: BelowMinimum) -> RewardPolicy {
+        RewardPolicy {
+            max_per_account: max.map(U256::from),
+            min_per_account: min.map(U256::from),
+            below_minimum,
+        }
+    }
+
+    fn apply(policy: &RewardPolicy, total: u64, carried: u64) -> (U256, U256) {
+        policy.apply(U256::from(total), U256::from(carried)).unwrap()
+    }
+
+    /// One epoch's aggregated rewards in `TOKEN`.
+    fn epoch(amounts: &[(Address, u64)]) -> BTreeMap<Address, BTreeMap<Address, TokenRewards>> {
+        amounts
+            .iter()
+            .map(|(account, amount)| {
+                let rewards = TokenRewards { total: U256::from(*amount), sources: vec![] };
+                (*account, BTreeMap::from([(TOKEN, rewards)]))
+            })
+            .collect()
+    }
+
+    /// The tree values paid this epoch.
+    fn values(rewards: &BTreeMap<Address, BTreeMap<Address, TokenRewards>>) -> Vec<Vec<String>> {
+        crate::epoch_values(rewards).0
+    }
+
+    fn claimable(values: &[Vec<String>]) -> BTreeMap<(Address, Address), U256> {
+        values
+            .iter()
+            .map(|value| {
+                let account = Address::from_str(&value[0]).unwrap();
+                let token = Address::from_str(&value[1]).unwrap();
+                ((account, token), U256::from_str(&value[2]).unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn cap_clamps_amount() {
+        let policy = policy(Some(100), None, BelowMinimum::Drop);
+        assert_eq!(apply(&policy, 150, 0), (U256::from(100), U256::ZERO));
+        assert_eq!(apply(&policy, 80, 50), (U256::from(100), U256::ZERO));
+        assert_eq!(apply(&policy, 60, 0), (U256::from(60), U256::ZERO));
+    }
+
+    #[test]
+    fn below_minimum_is_dropped() {
+        let policy = policy(None, Some(100), BelowMinimum::Drop);
+        assert_eq!(apply(&policy, 99, 0), (U256::ZERO, U256::ZERO));
+        assert_eq!(apply(&policy, 100, 0), (U256::from(100), U256::ZERO));
+        assert_eq!(apply(&policy, 0, 0), (U256::ZERO, U256::ZERO));
+    }
+
+    #[test]
+    fn below_minimum_is_carried_over() {
+        let policy = policy(None, Some(100), BelowMinimum::CarryOver);
+        assert_eq!(apply(&policy, 60, 0), (U256::ZERO, U256::from(60)));
+        assert_eq!(apply(&policy, 50, 60), (U256::from(110), U256::ZERO));
+    }
+
+    #[test]
+    fn carry_over_across_epochs_into_cumulative_tree() {
+        let registry =
+            SourceRegistry::new().with_policy(policy(None, Some(100), BelowMinimum::CarryOver));
+
+        // Epoch 1: A and C are below the minimum and carried over.
+        let mut rewards = epoch(&[(A, 60), (B, 150), (C, 40)]);
+        let carried = registry.apply_policy(&mut rewards, &BTreeMap::new()).unwrap();
+        assert_eq!(
+            carried,
+            BTreeMap::from([((A, TOKEN), U256::from(60)), ((C, TOKEN), U256::from(40))])
+        );
+        let tree = values(&rewards);
+        assert_eq!(claimable(&tree), BTreeMap::from([((B, TOKEN), U256::from(150))]));
+
+        // Epoch 2: A's carried amount reaches the minimum, B earns nothing new
+        // and C earns nothing, so stays carried.
+        let previous = PreviousTree {
+            ipfs_hash: String::new(),
+            claimable: claimable(&tree),
+            carried_over: carried,
+            snapshot_blocks: BTreeMap::new(),
+        };
+        let mut rewards = epoch(&[(A, 50)]);
+        let carried = registry.apply_policy(&mut rewards, &previous.carried_over).unwrap();
+        assert_eq!(carried, BTreeMap::from([((C, TOKEN), U256::from(40))]));
+        let tree = add_previous_claimable(values(&rewards), &previous).unwrap();
+        assert_eq!(
+            claimable(&tree),
+            BTreeMap::from([((A, TOKEN), U256::from(110)), ((B, TOKEN), U256::from(150))])
+        );
+    }
+}
@@ -0,0 +1,303 @@
+use crate::bindings::host::get_evm_chain_config;
+use alloy_network::Ethereum;
+use alloy_provider::{Provider, RootProvider};
+use alloy_rpc_types::{eth::TransactionRequest, BlockId, BlockNumberOrTag, Filter, Log};
+use alloy_transport::{RpcError, TransportError};
+use anyhow::Result;
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, BTreeSet, HashMap},
+    future::Future,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+use wavs_wasi_utils::evm::{
+    alloy_primitives::{Address, Bytes},
+    new_evm_provider,
+};
+
+/// Retry and rate limiting settings applied to every RPC request.
+#[derive(Clone, Debug)]
+pub struct RpcConfig {
+    /// Number of times a failed request is retried.
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled on each subsequent retry.
+    pub backoff_ms: u64,
+    /// Maximum requests per second per chain, if limited.
+    pub requests_per_second: Option<u32>,
+}
+
+impl Default for RpcConfig {
+    fn default() -> Self {
+        Self { max_retries: 3, backoff_ms: 500, requests_per_second: None }
+    }
+}
+
+/// Providers shared across sources, keyed by chain name.
+pub struct ProviderCache {
+    config: RpcConfig,
+    providers: RefCell<HashMap<String, ChainProvider>>,
+}
+
+impl ProviderCache {
+    pub fn new(config: RpcConfig) -> Self {
+        Self { config, providers: RefCell::new(HashMap::new()) }
+    }
+
+    /// Get the provider for a chain configured in WAVS, creating it on first use.
+    pub fn get(&self, chain_name: &str) -> Result<ChainProvider> {
+        if let Some(provider) = self.providers.borrow().get(chain_name) {
+            return Ok(provider.clone());
+        }
+
+        let chain_config = get_evm_chain_config(chain_name)
+            .ok_or(anyhow::anyhow!("Failed to get chain config for {}", chain_name))?;
+        let http_endpoint = chain_config
+            .http_endpoint
+            .ok_or(anyhow::anyhow!("No HTTP endpoint configured for chain {}", chain_name))?;
+        let provider = ChainProvider {
+            inner: Rc::new(ChainProviderInner {
+                provider: new_evm_provider::<Ethereum>(http_endpoint),
+                config: self.config.clone(),
+                next_request: Cell::new(None),
+            }),
+        };
+
+        self.providers.borrow_mut().insert(chain_name.to_string(), provider.clone());
+        Ok(provider)
+    }
+    /// Get the last block at or before `timestamp` on each chain, so that every
+    /// operator handling a cron trigger snapshots the same blocks.
+    pub async fn get_blocks_at_timestamp(
+        &self,
+        chains: &BTreeSet<String>,
+        timestamp: u64,
+    ) -> Result<BTreeMap<String, u64>> {
+        let mut blocks = BTreeMap::new();
+        for chain_name in chains {
+            let block = self.get(chain_name)?.get_block_at_timestamp(timestamp).await?;
+            blocks.insert(chain_name.clone(), block);
+        }
+        Ok(blocks)
+    }
+}
+
+/// A provider for one chain, shared by every source on it. All requests go
+/// through [`ChainProvider::request`], which applies retries and rate limiting.
+#[derive(Clone)]
+pub struct ChainProvider {
+    inner: Rc<ChainProviderInner>,
+}
+
+struct ChainProviderInner {
+    provider: RootProvider<Ethereum>,
+    config: RpcConfig,
+    /// Earliest time the next request may be sent, when rate limited.
+    next_request: Cell<Option<Instant>>,
+}
+
+impl ChainProvider {
+    /// Execute `eth_call` at a block.
+    pub async fn call(&self, tx: TransactionRequest, block: u64) -> Result<Bytes> {
+        let provider = &self.inner.provider;
+        let tx = &tx;
+        self.request(move || async move {
+            Ok(provider.call(tx.clone()).block(BlockId::number(block)).await?)
+        })
+        .await
+    }
+
+    /// Get logs matching a filter.
+    pub async fn get_logs(&self, filter: &Filter) -> Result<Vec<Log>> {
+        let provider = &self.inner.provider;
+        self.request(move || async move { Ok(provider.get_logs(filter).await?) }).await
+    }
+
+    /// Get the code deployed at an address at a block.
+    pub async fn get_code_at(&self, address: Address, block: u64) -> Result<Bytes> {
+        let provider = &self.inner.provider;
+        self.request(move || async move {
+            Ok(provider.get_code_at(address).block_id(BlockId::number(block)).await?)
+        })
+        .await
+    }
+
+    /// Get the last block with a timestamp at or before `timestamp`.
+    pub async fn get_block_at_timestamp(&self, timestamp: u64) -> Result<u64> {
+        let provider = &self.inner.provider;
+        let latest =
+            self.request(move || async move { Ok(provider.get_block_number().await?) }).await?;
+        find_block_at_timestamp(latest, timestamp, |block| self.get_block_timestamp(block)).await
+    }
+
+    /// Get the timestamp of a block.
+    async fn get_block_timestamp(&self, block: u64) -> Result<u64> {
+        let provider = &self.inner.provider;
+        let block = self
+            .request(move || async move {
+                Ok(provider.get_block_by_number(BlockNumberOrTag::Number(block)).await?)
+            })
+            .await?
+            .ok_or(anyhow::anyhow!("Block {} not found", block))?;
+        Ok(block.header.timestamp)
+    }
+
+    /// Run a request, waiting for the rate limit and retrying transport
+    /// failures and rate limits with exponential backoff. Other errors, such
+    /// as `eth_call` reverts, are returned immediately.
+    pub async fn request<T, F, Fut>(&self, f: F) -> Result<T>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let config = &self.inner.config;
+        let mut attempt = 0;
+        loop {
+            self.wait_for_rate_limit().await;
+            match f().await {
+                Ok(value) => return Ok(value),
+                Err(e) if attempt < config.max_retries && is_retryable(&e) => {
+                    let backoff = config.backoff_ms.saturating_mul(1 << attempt.min(16));
+                    eprintln!("RPC request failed, retrying in {}ms: {}", backoff, e);
+                    wstd::task::sleep(wstd::time::Duration::from_millis(backoff)).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Reserve the next request slot and sleep until it arrives.
+    async fn wait_for_rate_limit(&self) {
+        let Some(requests_per_second) = self.inner.config.requests_per_second else {
+            return;
+        };
+        let interval = Duration::from_secs(1) / requests_per_second.max(1);
+
+        let now = Instant::now();
+        let slot = match self.inner.next_request.get() {
+            Some(next) if next > now => next,
+            _ => now,
+        };
+        self.inner.next_request.set(Some(slot + interval));
+
+        let wait = slot - now;
+        if !wait.is_zero() {
+            wstd::task::sleep(wstd::time::Duration::from_millis(wait.as_millis() as u64)).await;
+        }
+    }
+}
+
+/// Binary search for the last block at or before `timestamp`, up to `latest`.
+///
+/// A block after `timestamp` must already exist, so the result doesn't depend
+/// on how far the node has synced.
+async fn find_block_at_timestamp<F, Fut>(
+    latest: u64,
+    timestamp: u64,
+    block_timestamp: F,
+) -> Result<u64>
+where
+    F: Fn(u64) -> Fut,
+    Fut: Future<Output = Result<u64>>,
+{
+    if block_timestamp(latest).await? <= timestamp {
+        return Err(anyhow::anyhow!(
+            "No block after timestamp {} yet; latest block is {}",
+            timestamp,
+            latest
+        ));
+    }
+    if block_timestamp(0).await? > timestamp {
+        return Err(anyhow::anyhow!("No block at or before timestamp {}", timestamp));
+    }
+
+    // block_timestamp(low) <= timestamp < block_timestamp(high)
+    let (mut low, mut high) = (0, latest);
+    while high - low > 1 {
+        let mid = low + (high - low) / 2;
+        if block_timestamp(mid).await? <= timestamp {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    Ok(low)
+}
+
+/// Whether a failed request may succeed if sent again.
+///
+/// Transport failures, empty and malformed responses and rate limit errors
+/// are. Any other JSON-RPC error response, such as an `eth_call` revert, is
+/// deterministic at a pinned block.
+fn is_retryable(error: &anyhow::Error) -> bool {
+    match error.downcast_ref::<TransportError>() {
+        Some(RpcError::ErrorResp(payload)) => payload.is_retry_err(),
+        Some(RpcError::Transport(_) | RpcError::NullResp | RpcError::DeserError { .. }) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_transport::TransportErrorKind;
+    use futures::executor::block_on;
+
+    fn error_response(code: i64, message: &str) -> anyhow::Error {
+        let payload = format!(r#"{{"code":{},"message":"{}"}}"#, code, message);
+        <TransportError>::ErrorResp(serde_json::from_str(&payload).unwrap()).into()
+    }
+
+    #[test]
+    fn reverts_are_not_retried() {
+        assert!(!is_retryable(&error_response(3, "execution reverted")));
+        assert!(!is_retryable(&error_response(-32000, "execution reverted: not staked")));
+    }
+
+    #[test]
+    fn rate_limits_and_transport_failures_are_retried() {
+        assert!(is_retryable(&error_response(429, "Too Many Requests")));
+        assert!(is_retryable(&error_response(-32005, "limit exceeded")));
+        assert!(is_retryable(&TransportErrorKind::custom_str("connection reset").into()));
+        assert!(is_retryable(&<TransportError>::NullResp.into()));
+    }
+
+    #[test]
+    fn other_errors_are_not_retried() {
+        assert!(!is_retryable(&anyhow::anyhow!("invalid input")));
+    }
+
+    /// Block timestamps 12 seconds apart from 1000, with a gap after block 5.
+    async fn block_timestamp(block: u64) -> Result<u64> {
+        Ok(1000 + block * 12 + if block > 5 { 600 } else { 0 })
+    }
+
+    fn block_at(latest: u64, timestamp: u64) -> Result<u64> {
+        block_on(find_block_at_timestamp(latest, timestamp, block_timestamp))
+    }
+
+    #[test]
+    fn finds_last_block_at_or_before_timestamp() {
+        assert_eq!(block_at(100, 1000).unwrap(), 0);
+        assert_eq!(block_at(100, 1011).unwrap(), 0);
+        assert_eq!(block_at(100, 1012).unwrap(), 1);
+        assert_eq!(block_at(100, 1300).unwrap(), 5);
+        assert_eq!(block_at(100, 1672).unwrap(), 6);
+        assert_eq!(block_at(100, 1000 + 99 * 12 + 600).unwrap(), 99);
+    }
+
+    #[test]
+    fn result_does_not_depend_on_latest_block() {
+        let timestamp = 1000 + 40 * 12 + 605;
+        assert_eq!(block_at(41, timestamp).unwrap(), 40);
+        assert_eq!(block_at(1_000_000, timestamp).unwrap(), 40);
+    }
+
+    #[test]
+    fn timestamps_outside_the_chain_are_rejected() {
+        assert!(block_at(10, 999).is_err());
+        // The latest block is not after the timestamp yet.
+        assert!(block_at(10, 1000 + 10 * 12 + 600).is_err());
+    }
+}
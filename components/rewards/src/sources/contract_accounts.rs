@@ -0,0 +1,344 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    str::FromStr,
+};
+use wavs_wasi_utils::evm::alloy_primitives::{Address, Bytes, U256};
+use wit_bindgen_rt::async_support::futures;
+
+use crate::provider::ChainProvider;
+
+use super::Source;
+
+/// Code prefix of an EIP-7702 delegation, which an EOA can still sign with.
+const DELEGATION_PREFIX: [u8; 3] = [0xef, 0x01, 0x00];
+
+/// Looks up the code deployed at an address.
+#[async_trait(?Send)]
+pub trait CodeProvider {
+    /// Get the code deployed at an address at a block.
+    async fn get_code_at(&self, address: Address, block: u64) -> Result<Bytes>;
+}
+
+#[async_trait(?Send)]
+impl CodeProvider for ChainProvider {
+    async fn get_code_at(&self, address: Address, block: u64) -> Result<Bytes> {
+        ChainProvider::get_code_at(self, address, block).await
+    }
+}
+
+/// How accounts with contract code (e.g. Safe wallets, marketplaces or
+/// staking vaults) are treated, since they may not be able to claim.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum ContractAccounts {
+    /// Reward contract accounts like any other account.
+    #[default]
+    Keep,
+    /// Leave contract accounts out.
+    Exclude,
+    /// Credit each contract account's rewards to its beneficiary. Contract
+    /// accounts without a beneficiary are left out.
+    Redirect {
+        /// Beneficiary address, keyed by contract address.
+        beneficiaries: BTreeMap<String, String>,
+    },
+}
+
+impl ContractAccounts {
+    /// Get metadata about the mode.
+    pub fn get_metadata(&self) -> serde_json::Value {
+        match self {
+            ContractAccounts::Keep => serde_json::json!({ "mode": "keep" }),
+            ContractAccounts::Exclude => serde_json::json!({ "mode": "exclude" }),
+            ContractAccounts::Redirect { beneficiaries } => serde_json::json!({
+                "mode": "redirect",
+                "beneficiaries": beneficiaries,
+            }),
+        }
+    }
+}
+
+/// Check each account of another source with `eth_getCode`, and exclude or
+/// redirect the rewards of accounts that are contracts.
+pub struct ContractAccountSource {
+    /// Source whose accounts are checked.
+    inner: Box<dyn Source>,
+    /// Provider for the source's chain.
+    provider: Box<dyn CodeProvider>,
+    /// Snapshot block code is checked at.
+    pub block: u64,
+    /// How contract accounts are treated.
+    pub mode: ContractAccounts,
+    /// Beneficiary per contract address, when redirecting.
+    beneficiaries: BTreeMap<Address, Address>,
+    /// Whether each account checked so far is a contract.
+    is_contract: RefCell<HashMap<Address, bool>>,
+}
+
+impl ContractAccountSource {
+    pub fn new(
+        inner: Box<dyn Source>,
+        provider: impl CodeProvider + 'static,
+        block: u64,
+        mode: ContractAccounts,
+    ) -> Result<Self> {
+        let mut beneficiaries = BTreeMap::new();
+        if let ContractAccounts::Redirect { beneficiaries: configured } = &mode {
+            for (contract, beneficiary) in configured {
+                beneficiaries.insert(Address::from_str(contract)?, Address::from_str(beneficiary)?);
+            }
+        }
+
+        Ok(Self {
+            inner,
+            provider: Box::new(provider),
+            block,
+            mode,
+            beneficiaries,
+            is_contract: RefCell::new(HashMap::new()),
+        })
+    }
+}
+
+#[async_trait(?Send)]
+impl Source for ContractAccountSource {
+    fn get_name(&self) -> &str {
+        self.inner.get_name()
+    }
+
+    async fn get_accounts(&self) -> Result<Vec<String>> {
+        let accounts = self.inner.get_accounts().await?;
+        let addresses = parse_addresses(&accounts)?;
+        let is_contract = self.query_contracts(&addresses).await?;
+
+        let mut eligible = BTreeSet::new();
+        for address in addresses {
+            if !is_contract[&address] {
+                eligible.insert(address);
+            } else if let Some(beneficiary) = self.beneficiary(&address) {
+                eligible.insert(beneficiary);
+            }
+        }
+        Ok(eligible.into_iter().map(|account| account.to_string()).collect())
+    }
+
+    async fn get_rewards(&self, account: &str) -> Result<U256> {
+        let rewards = self.get_rewards_for_accounts(&[account.to_string()]).await?;
+        Ok(rewards.get(account).copied().unwrap_or_default())
+    }
+
+    async fn get_rewards_for_accounts(&self, accounts: &[String]) -> Result<HashMap<String, U256>> {
+        let requested = parse_addresses(accounts)?;
+
+        // Contracts with a beneficiary are queried too, so their rewards are
+        // redirected even if they are not among `accounts`.
+        let addresses = requested
+            .iter()
+            .chain(self.beneficiaries.keys())
+            .copied()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        let queried = addresses.iter().map(|address| address.to_string()).collect::<Vec<_>>();
+        let is_contract = self.query_contracts(&addresses).await?;
+        let inner_rewards = self.inner.get_rewards_for_accounts(&queried).await?;
+
+        let mut credited_rewards: HashMap<Address, U256> = HashMap::new();
+        for (account, address) in queried.iter().zip(addresses) {
+            let credited = match (is_contract[&address], self.beneficiary(&address)) {
+                (false, _) => address,
+                (true, Some(beneficiary)) => beneficiary,
+                (true, None) => continue,
+            };
+
+            let amount = inner_rewards.get(account).copied().unwrap_or_default();
+            let total = credited_rewards.entry(credited).or_default();
+            *total =
+                total.checked_add(amount).ok_or(anyhow::anyhow!("Redirected rewards overflow"))?;
+        }
+
+        Ok(accounts
+            .iter()
+            .cloned()
+            .zip(
+                requested
+                    .iter()
+                    .map(|address| credited_rewards.get(address).copied().unwrap_or_default()),
+            )
+            .collect())
+    }
+
+    async fn get_metadata(&self) -> Result<serde_json::Value> {
+        let mut metadata = self.inner.get_metadata().await?;
+        if let Some(metadata) = metadata.as_object_mut() {
+            metadata.insert("contract_accounts".to_string(), self.mode.get_metadata());
+        }
+        Ok(metadata)
+    }
+}
+
+impl ContractAccountSource {
+    /// The account a contract's rewards are credited to, if any.
+    fn beneficiary(&self, contract: &Address) -> Option<Address> {
+        match self.mode {
+            ContractAccounts::Keep => Some(*contract),
+            ContractAccounts::Exclude => None,
+            ContractAccounts::Redirect { .. } => self.beneficiaries.get(contract).copied(),
+        }
+    }
+
+    /// Check which of `addresses` have contract code, querying each address
+    /// only once.
+    async fn query_contracts(&self, addresses: &[Address]) -> Result<HashMap<Address, bool>> {
+        let unchecked = addresses
+            .iter()
+            .filter(|address| !self.is_contract.borrow().contains_key(*address))
+            .copied()
+            .collect::<BTreeSet<_>>();
+
+        let codes = futures::future::join_all(
+            unchecked.iter().map(|address| self.provider.get_code_at(*address, self.block)),
+        )
+        .await;
+        for (address, code) in unchecked.into_iter().zip(codes) {
+            self.is_contract.borrow_mut().insert(address, is_contract_code(&code?));
+        }
+
+        let is_contract = self.is_contract.borrow();
+        Ok(addresses.iter().map(|address| (*address, is_contract[address])).collect())
+    }
+}
+
+/// Whether deployed code makes an account a contract. EOAs have no code, or
+/// an EIP-7702 delegation.
+fn is_contract_code(code: &[u8]) -> bool {
+    !code.is_empty() && !code.starts_with(&DELEGATION_PREFIX)
+}
+
+fn parse_addresses(accounts: &[String]) -> Result<Vec<Address>> {
+    Ok(accounts.iter().map(|account| Address::from_str(account)).collect::<Result<Vec<_>, _>>()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    const HOLDER: Address = Address::repeat_byte(0x01);
+    const SAFE: Address = Address::repeat_byte(0x02);
+    const VAULT: Address = Address::repeat_byte(0x03);
+    const DELEGATED: Address = Address::repeat_byte(0x04);
+    const OWNER: Address = Address::repeat_byte(0x05);
+
+    /// Pays fixed rewards to fixed accounts.
+    struct FixedSource(BTreeMap<Address, u64>);
+
+    #[async_trait(?Send)]
+    impl Source for FixedSource {
+        fn get_name(&self) -> &str {
+            "fixed"
+        }
+
+        async fn get_accounts(&self) -> Result<Vec<String>> {
+            Ok(self.0.keys().map(|account| account.to_string()).collect())
+        }
+
+        async fn get_rewards(&self, account: &str) -> Result<U256> {
+            let account = Address::from_str(account)?;
+            Ok(U256::from(self.0.get(&account).copied().unwrap_or_default()))
+        }
+
+        async fn get_metadata(&self) -> Result<serde_json::Value> {
+            Ok(serde_json::json!({}))
+        }
+    }
+
+    /// Serves fixed code per address; other addresses have none.
+    struct FixedCode(BTreeMap<Address, Bytes>);
+
+    #[async_trait(?Send)]
+    impl CodeProvider for FixedCode {
+        async fn get_code_at(&self, address: Address, _block: u64) -> Result<Bytes> {
+            Ok(self.0.get(&address).cloned().unwrap_or_default())
+        }
+    }
+
+    /// HOLDER and OWNER are EOAs, DELEGATED is an EOA with an EIP-7702
+    /// delegation, and SAFE and VAULT are contracts.
+    fn source(rewards: &[(Address, u64)], mode: ContractAccounts) -> ContractAccountSource {
+        let delegation = [DELEGATION_PREFIX.as_slice(), VAULT.as_slice()].concat();
+        let code = FixedCode(BTreeMap::from([
+            (SAFE, Bytes::from_static(&[0x60, 0x80])),
+            (VAULT, Bytes::from_static(&[0x60, 0x80])),
+            (DELEGATED, Bytes::from(delegation)),
+        ]));
+        let inner = FixedSource(rewards.iter().copied().collect());
+        ContractAccountSource::new(Box::new(inner), code, 100, mode).unwrap()
+    }
+
+    fn redirect(beneficiaries: &[(Address, Address)]) -> ContractAccounts {
+        ContractAccounts::Redirect {
+            beneficiaries: beneficiaries
+                .iter()
+                .map(|(contract, beneficiary)| (contract.to_string(), beneficiary.to_string()))
+                .collect(),
+        }
+    }
+
+    fn accounts(source: &ContractAccountSource) -> Vec<Address> {
+        let accounts = block_on(source.get_accounts()).unwrap();
+        parse_addresses(&accounts).unwrap()
+    }
+
+    fn rewards(source: &ContractAccountSource, accounts: &[Address]) -> Vec<u64> {
+        let accounts = accounts.iter().map(|account| account.to_string()).collect::<Vec<_>>();
+        let rewards = block_on(source.get_rewards_for_accounts(&accounts)).unwrap();
+        accounts.iter().map(|account| rewards[account].to::<u64>()).collect()
+    }
+
+    #[test]
+    fn exclude_leaves_contracts_out() {
+        let source =
+            source(&[(HOLDER, 10), (SAFE, 20), (DELEGATED, 30)], ContractAccounts::Exclude);
+        assert_eq!(accounts(&source), vec![HOLDER, DELEGATED]);
+        assert_eq!(rewards(&source, &[HOLDER, SAFE, DELEGATED]), vec![10, 0, 30]);
+    }
+
+    #[test]
+    fn redirect_credits_beneficiaries() {
+        // VAULT has no beneficiary, so it is left out.
+        let source = source(&[(HOLDER, 10), (SAFE, 20), (VAULT, 40)], redirect(&[(SAFE, OWNER)]));
+        assert_eq!(accounts(&source), vec![HOLDER, OWNER]);
+        assert_eq!(rewards(&source, &[HOLDER, OWNER, SAFE, VAULT]), vec![10, 20, 0, 0]);
+    }
+
+    #[test]
+    fn redirect_adds_to_beneficiaries_that_hold_the_token() {
+        let source = source(
+            &[(HOLDER, 10), (SAFE, 20), (VAULT, 40)],
+            redirect(&[(SAFE, HOLDER), (VAULT, HOLDER)]),
+        );
+        assert_eq!(accounts(&source), vec![HOLDER]);
+        assert_eq!(rewards(&source, &[HOLDER]), vec![70]);
+    }
+
+    #[test]
+    fn delegated_eoas_are_not_contracts() {
+        // A beneficiary configured for an EOA is ignored.
+        let source = source(&[(DELEGATED, 30)], redirect(&[(DELEGATED, OWNER)]));
+        assert_eq!(accounts(&source), vec![DELEGATED]);
+        assert_eq!(rewards(&source, &[DELEGATED, OWNER]), vec![30, 0]);
+    }
+
+    #[test]
+    fn contract_code() {
+        assert!(!is_contract_code(&[]));
+        assert!(!is_contract_code(&[[0xef, 0x01, 0x00].as_slice(), VAULT.as_slice()].concat()));
+        assert!(is_contract_code(&[0x60, 0x80]));
+        assert!(is_contract_code(&[0xef, 0x01]));
+    }
+}
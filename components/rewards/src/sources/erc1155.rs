@@ -0,0 +1,292 @@
+use alloy_rpc_types::{Filter, Log};
+use alloy_sol_types::{sol, SolEvent};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::{
+    cell::OnceCell,
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+};
+use wavs_wasi_utils::evm::alloy_primitives::{Address, U256};
+
+use crate::provider::ChainProvider;
+
+use super::{logs::get_logs_chunked, Source};
+
+/// Compute rewards from an ERC1155 token, with a separate rate per token ID.
+///
+/// Balances are rebuilt by replaying `TransferSingle` and `TransferBatch` logs
+/// from `from_block`, which should be at or before the contract deployment.
+/// Token IDs without a rate are ignored.
+pub struct Erc1155Source {
+    /// Chain the contract is deployed on.
+    pub chain_name: String,
+    /// Provider for the chain.
+    provider: ChainProvider,
+    /// Snapshot block all queries are pinned to.
+    pub block: u64,
+    /// Contract address.
+    pub address: Address,
+    /// Rewards per unit held, by token ID.
+    pub rates: BTreeMap<U256, U256>,
+    /// Block to start replaying transfer logs from.
+    pub from_block: u64,
+    /// Number of blocks requested per `eth_getLogs` call.
+    pub chunk_size: u64,
+    /// Balance of each rewarded token ID per account, computed once from the logs.
+    balances: OnceCell<HashMap<Address, BTreeMap<U256, U256>>>,
+}
+
+impl Erc1155Source {
+    pub fn new(
+        chain_name: &str,
+        provider: ChainProvider,
+        block: u64,
+        address: &str,
+        rates: BTreeMap<U256, U256>,
+        from_block: u64,
+        chunk_size: u64,
+    ) -> Result<Self> {
+        let contract = Address::from_str(address)?;
+        Ok(Self {
+            chain_name: chain_name.to_string(),
+            provider,
+            block,
+            address: contract,
+            rates,
+            from_block,
+            chunk_size,
+            balances: OnceCell::new(),
+        })
+    }
+}
+
+#[async_trait(?Send)]
+impl Source for Erc1155Source {
+    fn get_name(&self) -> &str {
+        "ERC1155"
+    }
+
+    async fn get_accounts(&self) -> Result<Vec<String>> {
+        let balances = self.query_balances().await?;
+        Ok(balances
+            .iter()
+            .filter(|(_, tokens)| tokens.values().any(|balance| !balance.is_zero()))
+            .map(|(account, _)| account.to_string())
+            .collect())
+    }
+
+    async fn get_rewards(&self, account: &str) -> Result<U256> {
+        let address = Address::from_str(account)?;
+        match self.query_balances().await?.get(&address) {
+            Some(tokens) => self.rewards_for_tokens(tokens),
+            None => Ok(U256::ZERO),
+        }
+    }
+
+    async fn get_rewards_for_accounts(&self, accounts: &[String]) -> Result<HashMap<String, U256>> {
+        let balances = self.query_balances().await?;
+        let mut rewards = HashMap::with_capacity(accounts.len());
+        for account in accounts {
+            let amount = match balances.get(&Address::from_str(account)?) {
+                Some(tokens) => self.rewards_for_tokens(tokens)?,
+                None => U256::ZERO,
+            };
+            rewards.insert(account.clone(), amount);
+        }
+        Ok(rewards)
+    }
+
+    async fn get_metadata(&self) -> Result<serde_json::Value> {
+        Ok(serde_json::json!({
+            "chain": self.chain_name,
+            "block": self.block,
+            "address": self.address.to_string(),
+            "rates": self
+                .rates
+                .iter()
+                .map(|(token_id, rate)| (token_id.to_string(), rate.to_string()))
+                .collect::<BTreeMap<_, _>>(),
+            "from_block": self.from_block,
+        }))
+    }
+}
+
+impl Erc1155Source {
+    /// Rewards for an account's balances, at each token ID's rate.
+    fn rewards_for_tokens(&self, tokens: &BTreeMap<U256, U256>) -> Result<U256> {
+        let mut rewards = U256::ZERO;
+        for (token_id, balance) in tokens {
+            let rate = self.rates.get(token_id).copied().unwrap_or_default();
+            let token_rewards =
+                balance.checked_mul(rate).ok_or(anyhow::anyhow!("ERC1155 rewards overflow"))?;
+            rewards = rewards
+                .checked_add(token_rewards)
+                .ok_or(anyhow::anyhow!("ERC1155 rewards overflow"))?;
+        }
+        Ok(rewards)
+    }
+
+    async fn query_balances(&self) -> Result<&HashMap<Address, BTreeMap<U256, U256>>> {
+        if let Some(balances) = self.balances.get() {
+            return Ok(balances);
+        }
+
+        let filter = Filter::new()
+            .address(self.address)
+            .event_signature(vec![TransferSingle::SIGNATURE_HASH, TransferBatch::SIGNATURE_HASH]);
+        let logs =
+            get_logs_chunked(&self.provider, filter, self.from_block, self.block, self.chunk_size)
+                .await?;
+
+        let balances = replay_transfers(&logs, &self.rates)?;
+        Ok(self.balances.get_or_init(|| balances))
+    }
+}
+
+/// Rebuild the balance of each rated token ID per account from
+/// `TransferSingle` and `TransferBatch` logs, in chain order. Token IDs
+/// without a rate are skipped.
+fn replay_transfers(
+    logs: &[Log],
+    rates: &BTreeMap<U256, U256>,
+) -> Result<HashMap<Address, BTreeMap<U256, U256>>> {
+    let mut balances: HashMap<Address, BTreeMap<U256, U256>> = HashMap::new();
+    let mut transfer = |from: Address, to: Address, id: U256, value: U256| {
+        if !rates.contains_key(&id) {
+            return;
+        }
+        if from != Address::ZERO {
+            let balance = balances.entry(from).or_default().entry(id).or_default();
+            *balance = balance.saturating_sub(value);
+        }
+        if to != Address::ZERO {
+            let balance = balances.entry(to).or_default().entry(id).or_default();
+            *balance = balance.saturating_add(value);
+        }
+    };
+
+    for log in logs {
+        let Some(topic0) = log.topic0() else {
+            continue;
+        };
+        if *topic0 == TransferSingle::SIGNATURE_HASH {
+            let event = TransferSingle::decode_log_data(log.data())?;
+            transfer(event.from, event.to, event.id, event.value);
+        } else if *topic0 == TransferBatch::SIGNATURE_HASH {
+            let event = TransferBatch::decode_log_data(log.data())?;
+            if event.ids.len() != event.values.len() {
+                return Err(anyhow::anyhow!("TransferBatch ids and values differ in length"));
+            }
+            for (id, value) in event.ids.into_iter().zip(event.values) {
+                transfer(event.from, event.to, id, value);
+            }
+        }
+    }
+
+    Ok(balances)
+}
+
+sol! {
+    event TransferSingle(address indexed operator, address indexed from, address indexed to, uint256 id, uint256 value);
+    event TransferBatch(address indexed operator, address indexed from, address indexed to, uint256[] ids, uint256[] values);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sources::logs::tests::log;
+
+    const CONTRACT: Address = Address::repeat_byte(0x55);
+    const OPERATOR: Address = Address::repeat_byte(0x0f);
+    const A: Address = Address::repeat_byte(0xaa);
+    const B: Address = Address::repeat_byte(0xbb);
+
+    fn ids(ids: &[u64]) -> Vec<U256> {
+        ids.iter().map(|id| U256::from(*id)).collect()
+    }
+
+    fn single(from: Address, to: Address, id: u64, value: u64) -> Log {
+        let event = TransferSingle {
+            operator: OPERATOR,
+            from,
+            to,
+            id: U256::from(id),
+            value: U256::from(value),
+        };
+        log(CONTRACT, event.encode_log_data())
+    }
+
+    fn batch(from: Address, to: Address, token_ids: &[u64], values: &[u64]) -> Log {
+        let event = TransferBatch {
+            operator: OPERATOR,
+            from,
+            to,
+            ids: ids(token_ids),
+            values: ids(values),
+        };
+        log(CONTRACT, event.encode_log_data())
+    }
+
+    /// Token IDs 1 and 2 are rated; 3 is not.
+    fn replay(logs: &[Log]) -> Result<HashMap<Address, BTreeMap<U256, U256>>> {
+        let rates =
+            BTreeMap::from([(U256::from(1), U256::from(10)), (U256::from(2), U256::from(5))]);
+        replay_transfers(logs, &rates)
+    }
+
+    fn balance(
+        balances: &HashMap<Address, BTreeMap<U256, U256>>,
+        account: Address,
+        id: u64,
+    ) -> u64 {
+        balances
+            .get(&account)
+            .and_then(|tokens| tokens.get(&U256::from(id)))
+            .map(|balance| balance.to::<u64>())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn mints_and_transfers() {
+        let balances = replay(&[
+            single(Address::ZERO, A, 1, 10),
+            batch(Address::ZERO, A, &[1, 2], &[5, 7]),
+            batch(A, B, &[1, 2], &[12, 3]),
+        ])
+        .unwrap();
+        assert_eq!(balance(&balances, A, 1), 3);
+        assert_eq!(balance(&balances, A, 2), 4);
+        assert_eq!(balance(&balances, B, 1), 12);
+        assert_eq!(balance(&balances, B, 2), 3);
+        assert!(!balances.contains_key(&Address::ZERO));
+    }
+
+    #[test]
+    fn burns_reduce_balances() {
+        let balances = replay(&[
+            single(Address::ZERO, A, 2, 10),
+            single(A, Address::ZERO, 2, 4),
+            batch(A, Address::ZERO, &[2], &[6]),
+        ])
+        .unwrap();
+        assert_eq!(balance(&balances, A, 2), 0);
+        assert!(!balances.contains_key(&Address::ZERO));
+    }
+
+    #[test]
+    fn unrated_ids_are_skipped() {
+        let balances =
+            replay(&[single(Address::ZERO, A, 3, 10), batch(Address::ZERO, B, &[3, 1], &[4, 2])])
+                .unwrap();
+        assert!(!balances.contains_key(&A));
+        assert_eq!(balances[&B].keys().copied().collect::<Vec<_>>(), ids(&[1]));
+        assert_eq!(balance(&balances, B, 1), 2);
+    }
+
+    #[test]
+    fn mismatched_batch_is_an_error() {
+        let error = replay(&[batch(Address::ZERO, A, &[1, 2], &[5])]).unwrap_err();
+        assert!(error.to_string().contains("differ in length"));
+    }
+}
@@ -0,0 +1,168 @@
+use alloy_sol_types::{sol, SolCall, SolType};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::{cell::OnceCell, collections::HashMap, str::FromStr};
+use wavs_wasi_utils::evm::alloy_primitives::{Address, Bytes, U256};
+
+use crate::provider::ChainProvider;
+
+use super::{
+    logs::{query_transfer_holders, HolderDiscovery},
+    multicall::{balance_of, batch_balance_of, call, DEFAULT_MULTICALL_CHUNK_SIZE},
+    Source,
+};
+
+/// Compute rewards from an ERC20 token, proportional to each holder's balance.
+pub struct Erc20Source {
+    /// Chain the contract is deployed on.
+    pub chain_name: String,
+    /// Provider for the chain.
+    provider: ChainProvider,
+    /// Snapshot block all queries are pinned to.
+    pub block: u64,
+    /// Contract address.
+    pub address: Address,
+    /// Rewards per whole token (i.e. per `10^decimals` base units).
+    pub rewards_per_token: U256,
+    /// How holders are discovered.
+    pub discovery: HolderDiscovery,
+    /// Number of balance queries batched per Multicall3 call (0 disables batching).
+    pub batch_size: usize,
+    /// Token decimals, queried once from the contract.
+    decimals: OnceCell<u8>,
+}
+
+impl Erc20Source {
+    pub fn new(
+        chain_name: &str,
+        provider: ChainProvider,
+        block: u64,
+        address: &str,
+        rewards_per_token: U256,
+    ) -> Result<Self> {
+        let token_contract = Address::from_str(address)?;
+        Ok(Self {
+            chain_name: chain_name.to_string(),
+            provider,
+            block,
+            address: token_contract,
+            rewards_per_token,
+            discovery: HolderDiscovery::GetAllHolders,
+            batch_size: DEFAULT_MULTICALL_CHUNK_SIZE,
+            decimals: OnceCell::new(),
+        })
+    }
+
+    /// Use a different holder discovery mode.
+    pub fn with_discovery(mut self, discovery: HolderDiscovery) -> Self {
+        self.discovery = discovery;
+        self
+    }
+
+    /// Use a different Multicall3 batch size for balance queries.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+}
+
+#[async_trait(?Send)]
+impl Source for Erc20Source {
+    fn get_name(&self) -> &str {
+        "ERC20"
+    }
+
+    async fn get_accounts(&self) -> Result<Vec<String>> {
+        let holders = self.query_holders().await?;
+        Ok(holders.into_iter().map(|h| h.to_string()).collect())
+    }
+
+    async fn get_rewards(&self, account: &str) -> Result<U256> {
+        let address = Address::from_str(account)?;
+        let balance = balance_of(&self.provider, self.block, self.address, address).await?;
+        self.rewards_for_balance(balance).await
+    }
+
+    async fn get_rewards_for_accounts(&self, accounts: &[String]) -> Result<HashMap<String, U256>> {
+        let owners =
+            accounts.iter().map(|a| Address::from_str(a)).collect::<Result<Vec<_>, _>>()?;
+        let balances =
+            batch_balance_of(&self.provider, self.block, self.address, &owners, self.batch_size)
+                .await?;
+
+        let mut rewards = HashMap::with_capacity(accounts.len());
+        for (account, balance) in accounts.iter().zip(balances) {
+            rewards.insert(account.clone(), self.rewards_for_balance(balance).await?);
+        }
+        Ok(rewards)
+    }
+
+    async fn get_metadata(&self) -> Result<serde_json::Value> {
+        Ok(serde_json::json!({
+            "chain": self.chain_name,
+            "block": self.block,
+            "address": self.address.to_string(),
+            "rewards_per_token": self.rewards_per_token.to_string(),
+            "decimals": self.query_decimals().await?,
+            "discovery": self.discovery.get_metadata(),
+        }))
+    }
+}
+
+impl Erc20Source {
+    async fn call(&self, input: Bytes) -> Result<Bytes> {
+        call(&self.provider, self.block, self.address, input).await
+    }
+
+    /// Rewards for a balance in base units, at `rewards_per_token` per whole token.
+    async fn rewards_for_balance(&self, balance: U256) -> Result<U256> {
+        let decimals = self.query_decimals().await?;
+        let unit = U256::from(10).pow(U256::from(decimals));
+        let rewards = balance
+            .checked_mul(self.rewards_per_token)
+            .ok_or(anyhow::anyhow!("ERC20 rewards overflow"))?
+            / unit;
+        Ok(rewards)
+    }
+
+    async fn query_decimals(&self) -> Result<u8> {
+        if let Some(decimals) = self.decimals.get() {
+            return Ok(*decimals);
+        }
+
+        let decimals_call = IERC20::decimalsCall {};
+        let result = self.call(decimals_call.abi_encode().into()).await?;
+        let decimals = IERC20::decimalsCall::abi_decode_returns(&result)?;
+
+        Ok(*self.decimals.get_or_init(|| decimals))
+    }
+
+    async fn query_holders(&self) -> Result<Vec<Address>> {
+        if let HolderDiscovery::TransferLogs { from_block, to_block, chunk_size } = self.discovery {
+            let holders = query_transfer_holders(
+                &self.provider,
+                self.address,
+                from_block,
+                to_block.unwrap_or(self.block),
+                chunk_size,
+            )
+            .await?;
+            return Ok(holders);
+        }
+
+        let holders_call = IRewardSourceToken::getAllHoldersCall {};
+        let result = self.call(holders_call.abi_encode().into()).await?.to_vec();
+
+        let holders: Vec<Address> = <sol! { address[] }>::abi_decode(&result)?;
+        Ok(holders)
+    }
+}
+
+sol! {
+    interface IERC20 {
+        function decimals() external view returns (uint8);
+    }
+    interface IRewardSourceToken {
+        function getAllHolders() external view returns (address[] memory);
+    }
+}
@@ -1,30 +1,76 @@
-use crate::bindings::host::get_evm_chain_config;
-use alloy_network::Ethereum;
-use alloy_provider::{Provider, RootProvider};
-use alloy_rpc_types::TransactionInput;
 use alloy_sol_types::{sol, SolCall, SolType};
 use anyhow::Result;
 use async_trait::async_trait;
-use std::str::FromStr;
-use wavs_wasi_utils::evm::{
-    alloy_primitives::{Address, TxKind, U256},
-    new_evm_provider,
-};
+use std::{collections::HashMap, str::FromStr};
+use wavs_wasi_utils::evm::alloy_primitives::{Address, U256};
+use wit_bindgen_rt::async_support::futures;
+
+use crate::{config::DEFAULT_WEIGHT_BPS, provider::ChainProvider};
 
-use super::Source;
+use super::{
+    logs::{query_token_owners, query_transfer_holders, HolderDiscovery},
+    multicall::{balance_of, batch_balance_of, call, DEFAULT_MULTICALL_CHUNK_SIZE},
+    multipliers::{TokenEnumeration, TokenMultipliers},
+    Source,
+};
 
 /// Compute rewards from an ERC721 token.
 pub struct Erc721Source {
+    /// Chain the contract is deployed on.
+    pub chain_name: String,
+    /// Provider for the chain.
+    provider: ChainProvider,
+    /// Snapshot block all queries are pinned to.
+    pub block: u64,
     /// Contract address.
     pub address: Address,
     /// Rewards per token.
     pub rewards_per_token: U256,
+    /// How holders are discovered.
+    pub discovery: HolderDiscovery,
+    /// Number of balance queries batched per Multicall3 call (0 disables batching).
+    pub batch_size: usize,
+    /// Per-token-ID multipliers, if tokens are not all rewarded the same.
+    pub multipliers: Option<TokenMultipliers>,
 }
 
 impl Erc721Source {
-    pub fn new(address: &str, rewards_per_token: U256) -> Self {
-        let nft_contract = Address::from_str(address).unwrap();
-        Self { address: nft_contract, rewards_per_token }
+    pub fn new(
+        chain_name: &str,
+        provider: ChainProvider,
+        block: u64,
+        address: &str,
+        rewards_per_token: U256,
+    ) -> Result<Self> {
+        let nft_contract = Address::from_str(address)?;
+        Ok(Self {
+            chain_name: chain_name.to_string(),
+            provider,
+            block,
+            address: nft_contract,
+            rewards_per_token,
+            discovery: HolderDiscovery::GetAllHolders,
+            batch_size: DEFAULT_MULTICALL_CHUNK_SIZE,
+            multipliers: None,
+        })
+    }
+
+    /// Use a different holder discovery mode.
+    pub fn with_discovery(mut self, discovery: HolderDiscovery) -> Self {
+        self.discovery = discovery;
+        self
+    }
+
+    /// Use a different Multicall3 batch size for balance queries.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Reward each token by its multiplier instead of the flat rate per NFT.
+    pub fn with_multipliers(mut self, multipliers: TokenMultipliers) -> Self {
+        self.multipliers = Some(multipliers);
+        self
     }
 }
 
@@ -36,65 +82,161 @@ impl Source for Erc721Source {
 
     async fn get_accounts(&self) -> Result<Vec<String>> {
         let holders = self.query_holders().await?;
-        Ok(holders)
+        Ok(holders.into_iter().map(|h| h.to_string()).collect())
     }
 
     async fn get_rewards(&self, account: &str) -> Result<U256> {
-        let address = Address::from_str(account).unwrap();
-        let nft_balance = self.query_nft_ownership(address).await?;
+        let address = Address::from_str(account)?;
+        if let Some(multipliers) = &self.multipliers {
+            return Ok(self.query_weighted_rewards(multipliers, &[address]).await?[0]);
+        }
+        let nft_balance = balance_of(&self.provider, self.block, self.address, address).await?;
         Ok(self.rewards_per_token * nft_balance)
     }
 
+    async fn get_rewards_for_accounts(&self, accounts: &[String]) -> Result<HashMap<String, U256>> {
+        let owners =
+            accounts.iter().map(|a| Address::from_str(a)).collect::<Result<Vec<_>, _>>()?;
+        if let Some(multipliers) = &self.multipliers {
+            let rewards = self.query_weighted_rewards(multipliers, &owners).await?;
+            return Ok(accounts.iter().cloned().zip(rewards).collect());
+        }
+        let nft_balances =
+            batch_balance_of(&self.provider, self.block, self.address, &owners, self.batch_size)
+                .await?;
+        Ok(accounts
+            .iter()
+            .cloned()
+            .zip(nft_balances.into_iter().map(|balance| self.rewards_per_token * balance))
+            .collect())
+    }
+
     async fn get_metadata(&self) -> Result<serde_json::Value> {
-        Ok(serde_json::json!({
+        let mut metadata = serde_json::json!({
+            "chain": self.chain_name,
+            "block": self.block,
             "address": self.address.to_string(),
             "rewards_per_token": self.rewards_per_token.to_string(),
-        }))
+            "discovery": self.discovery.get_metadata(),
+        });
+        if let Some(multipliers) = &self.multipliers {
+            metadata["multipliers"] = multipliers.get_metadata();
+        }
+        Ok(metadata)
     }
 }
 
 impl Erc721Source {
-    async fn query_nft_ownership(&self, owner: Address) -> Result<U256> {
-        let chain_config = get_evm_chain_config("local").unwrap();
-        let provider: RootProvider<Ethereum> =
-            new_evm_provider::<Ethereum>(chain_config.http_endpoint.unwrap());
-
-        let balance_call = IERC721::balanceOfCall { owner };
-        let tx = alloy_rpc_types::eth::TransactionRequest {
-            to: Some(TxKind::Call(self.address)),
-            input: TransactionInput { input: Some(balance_call.abi_encode().into()), data: None },
-            ..Default::default()
-        };
+    /// Rewards for the tokens each owner holds, at `rewards_per_token` scaled
+    /// by each token's multiplier.
+    async fn query_weighted_rewards(
+        &self,
+        multipliers: &TokenMultipliers,
+        owners: &[Address],
+    ) -> Result<Vec<U256>> {
+        let owned_tokens = self.query_owned_tokens(&multipliers.enumeration, owners).await?;
+
+        let mut rewards = Vec::with_capacity(owners.len());
+        for owner in owners {
+            let mut total = U256::ZERO;
+            for token_id in owned_tokens.get(owner).into_iter().flatten() {
+                let multiplier = U256::from(multipliers.get_multiplier(*token_id));
+                let token_rewards = self
+                    .rewards_per_token
+                    .checked_mul(multiplier)
+                    .ok_or(anyhow::anyhow!("ERC721 rewards overflow"))?
+                    / U256::from(DEFAULT_WEIGHT_BPS);
+                total = total
+                    .checked_add(token_rewards)
+                    .ok_or(anyhow::anyhow!("ERC721 rewards overflow"))?;
+            }
+            rewards.push(total);
+        }
+        Ok(rewards)
+    }
 
-        let result = provider.call(tx).await?;
+    /// Get the token IDs each of `owners` holds.
+    async fn query_owned_tokens(
+        &self,
+        enumeration: &TokenEnumeration,
+        owners: &[Address],
+    ) -> Result<HashMap<Address, Vec<U256>>> {
+        let mut owned_tokens: HashMap<Address, Vec<U256>> = HashMap::new();
+        match enumeration {
+            TokenEnumeration::TokenOfOwnerByIndex => {
+                let balances = batch_balance_of(
+                    &self.provider,
+                    self.block,
+                    self.address,
+                    owners,
+                    self.batch_size,
+                )
+                .await?;
+                for (owner, balance) in owners.iter().zip(balances) {
+                    let token_ids = futures::future::join_all(
+                        (0..u64::try_from(balance)?)
+                            .map(|index| self.query_token_of_owner_by_index(*owner, index)),
+                    )
+                    .await
+                    .into_iter()
+                    .collect::<Result<Vec<_>>>()?;
+                    owned_tokens.insert(*owner, token_ids);
+                }
+            }
+            TokenEnumeration::TransferLogs { from_block, chunk_size } => {
+                let token_owners = query_token_owners(
+                    &self.provider,
+                    self.address,
+                    *from_block,
+                    self.block,
+                    *chunk_size,
+                )
+                .await?;
+                for (token_id, owner) in token_owners {
+                    owned_tokens.entry(owner).or_default().push(token_id);
+                }
+            }
+        }
+        Ok(owned_tokens)
+    }
 
-        Ok(U256::from_be_slice(&result))
+    async fn query_token_of_owner_by_index(&self, owner: Address, index: u64) -> Result<U256> {
+        let token_call =
+            IERC721Enumerable::tokenOfOwnerByIndexCall { owner, index: U256::from(index) };
+        let result =
+            call(&self.provider, self.block, self.address, token_call.abi_encode().into()).await?;
+        Ok(IERC721Enumerable::tokenOfOwnerByIndexCall::abi_decode_returns(&result)?)
     }
 
-    async fn query_holders(&self) -> Result<Vec<String>> {
-        let chain_config = get_evm_chain_config("local").unwrap();
-        let provider: RootProvider<Ethereum> =
-            new_evm_provider::<Ethereum>(chain_config.http_endpoint.unwrap());
+    async fn query_holders(&self) -> Result<Vec<Address>> {
+        if let HolderDiscovery::TransferLogs { from_block, to_block, chunk_size } = self.discovery {
+            let holders = query_transfer_holders(
+                &self.provider,
+                self.address,
+                from_block,
+                to_block.unwrap_or(self.block),
+                chunk_size,
+            )
+            .await?;
+            return Ok(holders);
+        }
 
         let holders_call = IRewardSourceNft::getAllHoldersCall {};
-        let tx = alloy_rpc_types::eth::TransactionRequest {
-            to: Some(TxKind::Call(self.address)),
-            input: TransactionInput { input: Some(holders_call.abi_encode().into()), data: None },
-            ..Default::default()
-        };
-
-        let result = provider.call(tx).await?.to_vec();
+        let result =
+            call(&self.provider, self.block, self.address, holders_call.abi_encode().into())
+                .await?
+                .to_vec();
 
         let holders: Vec<Address> = <sol! { address[] }>::abi_decode(&result)?;
-        Ok(holders.into_iter().map(|h| h.to_string()).collect())
+        Ok(holders)
     }
 }
 
 sol! {
-    interface IERC721 {
-        function balanceOf(address owner) external view returns (uint256);
-    }
     interface IRewardSourceNft {
         function getAllHolders() external view returns (address[] memory);
     }
+    interface IERC721Enumerable {
+        function tokenOfOwnerByIndex(address owner, uint256 index) external view returns (uint256);
+    }
 }
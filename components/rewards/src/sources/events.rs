@@ -0,0 +1,440 @@
+use alloy_rpc_types::Filter;
+use anyhow::Result;
+use async_trait::async_trait;
+use std::{cell::OnceCell, collections::HashMap, str::FromStr};
+use wavs_wasi_utils::evm::alloy_primitives::{keccak256, Address, B256, U256};
+
+use crate::provider::ChainProvider;
+
+use super::{
+    logs::{get_logs_chunked, topic_to_address},
+    Source,
+};
+
+/// Compute rewards from occurrences of an arbitrary event, emitted by a
+/// contract between `from_block` and `to_block`.
+///
+/// Each event pays `rewards_per_event` to the account in its indexed topic at
+/// `account_topic`. When weighted, an event pays `rewards_per_event` per whole
+/// unit (`10^decimals`) of the 32-byte data word at `weight_index` instead.
+pub struct EventSource {
+    /// Chain the contract is deployed on.
+    pub chain_name: String,
+    /// Provider for the chain.
+    provider: ChainProvider,
+    /// Contract emitting the event.
+    pub address: Address,
+    /// Canonical event signature, e.g. `Deposit(address,uint256)`.
+    pub signature: String,
+    /// Hash of the event signature (topic 0).
+    pub topic0: B256,
+    /// Indexed topic holding the rewarded account (1 to 3).
+    pub account_topic: usize,
+    /// Rewards per event, or per whole unit of the weight if weighted.
+    pub rewards_per_event: U256,
+    /// Data word each event is weighted by, if any.
+    pub weight_index: Option<usize>,
+    /// Decimals of the weight, used when weighted.
+    pub decimals: u8,
+    /// First block events are counted from.
+    pub from_block: u64,
+    /// Last block events are counted to.
+    pub to_block: u64,
+    /// Number of blocks requested per `eth_getLogs` call.
+    pub chunk_size: u64,
+    /// Rewards per account, computed once from the logs.
+    rewards: OnceCell<HashMap<Address, U256>>,
+}
+
+impl EventSource {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        chain_name: &str,
+        provider: ChainProvider,
+        address: &str,
+        signature: &str,
+        account_topic: usize,
+        rewards_per_event: U256,
+        from_block: u64,
+        to_block: u64,
+        chunk_size: u64,
+    ) -> Result<Self> {
+        let contract = Address::from_str(address)?;
+        let signature = canonical_signature(signature)?;
+        if !(1..=3).contains(&account_topic) {
+            return Err(anyhow::anyhow!(
+                "Event account topic must be between 1 and 3, got {}",
+                account_topic
+            ));
+        }
+        let topic0 = keccak256(signature.as_bytes());
+        Ok(Self {
+            chain_name: chain_name.to_string(),
+            provider,
+            address: contract,
+            signature,
+            topic0,
+            account_topic,
+            rewards_per_event,
+            weight_index: None,
+            decimals: 0,
+            from_block,
+            to_block,
+            chunk_size,
+            rewards: OnceCell::new(),
+        })
+    }
+
+    /// Weight each event by the data word at `index` (counted from 0, over
+    /// the non-indexed fields), with `decimals` decimals.
+    pub fn with_weight(mut self, index: usize, decimals: u8) -> Self {
+        self.weight_index = Some(index);
+        self.decimals = decimals;
+        self
+    }
+}
+
+#[async_trait(?Send)]
+impl Source for EventSource {
+    fn get_name(&self) -> &str {
+        "events"
+    }
+
+    async fn get_accounts(&self) -> Result<Vec<String>> {
+        let rewards = self.query_rewards().await?;
+        Ok(rewards
+            .iter()
+            .filter(|(_, rewards)| !rewards.is_zero())
+            .map(|(account, _)| account.to_string())
+            .collect())
+    }
+
+    async fn get_rewards(&self, account: &str) -> Result<U256> {
+        let address = Address::from_str(account)?;
+        Ok(self.query_rewards().await?.get(&address).copied().unwrap_or_default())
+    }
+
+    async fn get_rewards_for_accounts(&self, accounts: &[String]) -> Result<HashMap<String, U256>> {
+        let rewards = self.query_rewards().await?;
+        accounts
+            .iter()
+            .map(|account| {
+                let address = Address::from_str(account)?;
+                Ok((account.clone(), rewards.get(&address).copied().unwrap_or_default()))
+            })
+            .collect()
+    }
+
+    async fn get_metadata(&self) -> Result<serde_json::Value> {
+        Ok(serde_json::json!({
+            "chain": self.chain_name,
+            "address": self.address.to_string(),
+            "event": self.signature,
+            "account_topic": self.account_topic,
+            "rewards_per_event": self.rewards_per_event.to_string(),
+            "weight_index": self.weight_index,
+            "decimals": self.decimals,
+            "from_block": self.from_block,
+            "to_block": self.to_block,
+        }))
+    }
+}
+
+impl EventSource {
+    async fn query_rewards(&self) -> Result<&HashMap<Address, U256>> {
+        if let Some(rewards) = self.rewards.get() {
+            return Ok(rewards);
+        }
+
+        if self.from_block > self.to_block {
+            return Err(anyhow::anyhow!(
+                "Event start block {} is after end block {}",
+                self.from_block,
+                self.to_block
+            ));
+        }
+
+        let filter = Filter::new().address(self.address).event_signature(self.topic0);
+        let logs = get_logs_chunked(
+            &self.provider,
+            filter,
+            self.from_block,
+            self.to_block,
+            self.chunk_size,
+        )
+        .await?;
+
+        let mut rewards: HashMap<Address, U256> = HashMap::new();
+        for log in logs {
+            let topic = log.topics().get(self.account_topic).ok_or(anyhow::anyhow!(
+                "{} has no indexed topic {}",
+                self.signature,
+                self.account_topic
+            ))?;
+            let account = topic_to_address(topic);
+            if account == Address::ZERO {
+                continue;
+            }
+
+            let weight = self.weight_index.map(|index| (index, self.decimals));
+            let event_rewards = rewards_for_event(&log.data().data, self.rewards_per_event, weight)
+                .map_err(|e| anyhow::anyhow!("{}: {}", self.signature, e))?;
+            let total = rewards.entry(account).or_default();
+            *total = total
+                .checked_add(event_rewards)
+                .ok_or(anyhow::anyhow!("Event rewards overflow"))?;
+        }
+
+        Ok(self.rewards.get_or_init(|| rewards))
+    }
+}
+
+/// Rewards for a single event, given its data. With a `weight` of
+/// `(index, decimals)`, `rewards_per_event` is paid per whole unit of the data
+/// word at `index`.
+fn rewards_for_event(
+    data: &[u8],
+    rewards_per_event: U256,
+    weight: Option<(usize, u8)>,
+) -> Result<U256> {
+    let Some((index, decimals)) = weight else {
+        return Ok(rewards_per_event);
+    };
+
+    let word = data
+        .get(index * 32..(index + 1) * 32)
+        .ok_or(anyhow::anyhow!("Event has no data word {} to weight by", index))?;
+    let unit = U256::from(10).pow(U256::from(decimals));
+    let rewards = U256::from_be_slice(word)
+        .checked_mul(rewards_per_event)
+        .ok_or(anyhow::anyhow!("Event rewards overflow"))?
+        / unit;
+    Ok(rewards)
+}
+
+/// Parse an event signature into its canonical form, e.g.
+/// `Deposit(address,uint256)`, whose hash is topic 0. Whitespace around types
+/// is dropped. Parameter names, `indexed` and aliases such as `uint` are
+/// rejected, since they would hash to a topic no log has.
+fn canonical_signature(signature: &str) -> Result<String> {
+    let invalid =
+        |reason: String| anyhow::anyhow!("Invalid event signature {}: {}", signature, reason);
+
+    let (name, params) = signature
+        .trim()
+        .split_once('(')
+        .ok_or_else(|| invalid("missing parameter list".to_string()))?;
+    let params = params
+        .strip_suffix(')')
+        .ok_or_else(|| invalid("parameter list is not closed".to_string()))?;
+    if !is_identifier(name) {
+        return Err(invalid(format!("{:?} is not an event name", name)));
+    }
+
+    let types = if params.trim().is_empty() {
+        vec![]
+    } else {
+        split_types(params)
+            .and_then(|types| types.into_iter().map(canonical_type).collect())
+            .map_err(|e| invalid(e.to_string()))?
+    };
+    Ok(format!("{}({})", name, types.join(",")))
+}
+
+/// The canonical form of one parameter type, with any array suffixes.
+fn canonical_type(ty: &str) -> Result<String> {
+    let ty = ty.trim();
+    let mut base = ty;
+    while let Some(array) = base.strip_suffix(']') {
+        let (element, length) =
+            array.rsplit_once('[').ok_or(anyhow::anyhow!("unbalanced brackets in {}", ty))?;
+        if !length.is_empty() && !parse_size(length).is_some_and(|length| length > 0) {
+            return Err(anyhow::anyhow!("invalid array length in {}", ty));
+        }
+        base = element;
+    }
+
+    let arrays = &ty[base.len()..];
+    if let Some(components) = base.strip_prefix('(').and_then(|tuple| tuple.strip_suffix(')')) {
+        let components =
+            split_types(components)?.into_iter().map(canonical_type).collect::<Result<Vec<_>>>()?;
+        return Ok(format!("({}){}", components.join(","), arrays));
+    }
+    if !is_elementary(base) {
+        return Err(anyhow::anyhow!("{:?} is not a canonical type", ty));
+    }
+    Ok(ty.to_string())
+}
+
+/// Split a comma-separated type list, leaving commas inside tuples alone.
+fn split_types(types: &str) -> Result<Vec<&str>> {
+    let mut split = vec![];
+    let (mut depth, mut start) = (0usize, 0);
+    for (i, c) in types.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth = depth.checked_sub(1).ok_or(anyhow::anyhow!("unbalanced parentheses"))?,
+            ',' if depth == 0 => {
+                split.push(&types[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if depth != 0 {
+        return Err(anyhow::anyhow!("unbalanced parentheses"));
+    }
+    split.push(&types[start..]);
+    Ok(split)
+}
+
+/// Whether `ty` is an elementary ABI type in canonical form, e.g. `uint256`
+/// rather than `uint`.
+fn is_elementary(ty: &str) -> bool {
+    let is_bits = |bits: &str| {
+        parse_size(bits).is_some_and(|bits| bits % 8 == 0 && (8..=256).contains(&bits))
+    };
+    match ty {
+        "address" | "bool" | "string" | "bytes" | "function" => true,
+        _ => {
+            if let Some(size) = ty.strip_prefix("bytes") {
+                parse_size(size).is_some_and(|size| (1..=32).contains(&size))
+            } else if let Some(bits) = ty.strip_prefix("uint").or_else(|| ty.strip_prefix("int")) {
+                is_bits(bits)
+            } else if let Some(fixed) =
+                ty.strip_prefix("ufixed").or_else(|| ty.strip_prefix("fixed"))
+            {
+                fixed.split_once('x').is_some_and(|(bits, decimals)| {
+                    is_bits(bits) && parse_size(decimals).is_some_and(|decimals| decimals <= 80)
+                })
+            } else {
+                false
+            }
+        }
+    }
+}
+
+/// Parse a size written without leading zeros, as in canonical types.
+fn parse_size(size: &str) -> Option<usize> {
+    let canonical = !size.is_empty()
+        && size.bytes().all(|b| b.is_ascii_digit())
+        && (size == "0" || !size.starts_with('0'));
+    canonical.then(|| size.parse().ok()).flatten()
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '$')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sources::logs::Transfer;
+    use alloy_sol_types::SolEvent;
+
+    /// ABI-encoded data words for `values`.
+    fn data(values: &[u64]) -> Vec<u8> {
+        values.iter().flat_map(|value| U256::from(*value).to_be_bytes::<32>()).collect()
+    }
+
+    #[test]
+    fn unweighted_events_pay_a_flat_rate() {
+        let rewards = rewards_for_event(&[], U256::from(7), None).unwrap();
+        assert_eq!(rewards, U256::from(7));
+    }
+
+    #[test]
+    fn weighted_events_read_the_indexed_word() {
+        let data = data(&[3, 2_500_000, 9]);
+        let rewards = rewards_for_event(&data, U256::from(10), Some((1, 6))).unwrap();
+        assert_eq!(rewards, U256::from(25));
+        let rewards = rewards_for_event(&data, U256::from(10), Some((0, 0))).unwrap();
+        assert_eq!(rewards, U256::from(30));
+        let rewards = rewards_for_event(&data, U256::from(10), Some((2, 0))).unwrap();
+        assert_eq!(rewards, U256::from(90));
+    }
+
+    #[test]
+    fn short_data_is_rejected() {
+        let data = data(&[3, 4]);
+        assert!(rewards_for_event(&data, U256::from(10), Some((2, 0))).is_err());
+        assert!(rewards_for_event(&data[..63], U256::from(10), Some((1, 0))).is_err());
+        assert!(rewards_for_event(&[], U256::from(10), Some((0, 0))).is_err());
+    }
+
+    #[test]
+    fn weighted_rewards_overflow_is_an_error() {
+        let data = U256::MAX.to_be_bytes::<32>();
+        assert!(rewards_for_event(&data, U256::from(2), Some((0, 0))).is_err());
+    }
+
+    #[test]
+    fn canonical_signatures_are_kept() {
+        for signature in [
+            "Deposit(address,uint256)",
+            "Ping()",
+            "Swap(address,int24,uint160,bytes32,bool)",
+            "Batch(uint256[],address[2][],bytes)",
+            "Order((address,uint256)[],(bytes4,(bool,string)))",
+            "Rate(ufixed128x18)",
+        ] {
+            assert_eq!(canonical_signature(signature).unwrap(), signature);
+        }
+    }
+
+    #[test]
+    fn whitespace_around_types_is_dropped() {
+        let signature = canonical_signature(" Deposit( address , uint256[] ) ").unwrap();
+        assert_eq!(signature, "Deposit(address,uint256[])");
+        assert_eq!(
+            keccak256(canonical_signature("Transfer(address, address, uint256)").unwrap()),
+            Transfer::SIGNATURE_HASH
+        );
+    }
+
+    #[test]
+    fn parameter_names_and_indexed_are_rejected() {
+        assert!(canonical_signature("Deposit(address indexed user, uint256 amount)").is_err());
+        assert!(canonical_signature("Deposit(address user,uint256)").is_err());
+        assert!(canonical_signature("Deposit(address indexed,uint256)").is_err());
+        assert!(canonical_signature("Deposit((address user,uint256))").is_err());
+    }
+
+    #[test]
+    fn type_aliases_are_rejected() {
+        for signature in [
+            "Deposit(address,uint)",
+            "Deposit(int)",
+            "Deposit(byte)",
+            "Deposit(fixed)",
+            "Deposit(ufixed)",
+            "Deposit(uint7)",
+            "Deposit(uint264)",
+            "Deposit(uint0256)",
+            "Deposit(bytes33)",
+            "Deposit(Uint256)",
+        ] {
+            assert!(canonical_signature(signature).is_err(), "{}", signature);
+        }
+    }
+
+    #[test]
+    fn malformed_signatures_are_rejected() {
+        for signature in [
+            "Deposit",
+            "Deposit(address",
+            "(address)",
+            "1Deposit(address)",
+            "Deposit(address,)",
+            "Deposit(uint256[0])",
+            "Deposit(uint256[01])",
+            "Deposit(uint256[)",
+            "Deposit((address)",
+            "Deposit(())",
+        ] {
+            assert!(canonical_signature(signature).is_err(), "{}", signature);
+        }
+    }
+}
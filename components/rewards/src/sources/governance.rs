@@ -0,0 +1,294 @@
+use alloy_rpc_types::{Filter, Log};
+use alloy_sol_types::{sol, SolEvent};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::{
+    cell::OnceCell,
+    collections::{BTreeSet, HashMap},
+    str::FromStr,
+};
+use wavs_wasi_utils::evm::alloy_primitives::{Address, U256};
+
+use crate::provider::ChainProvider;
+
+use super::{logs::get_logs_chunked, Source};
+
+/// Compute rewards for voting on an OpenZeppelin Governor, from its `VoteCast`
+/// and `VoteCastWithParams` logs between `from_block` and `to_block`.
+///
+/// Each vote pays `rewards_per_vote`, credited to the voter. When weighted by
+/// voting power, a vote pays `rewards_per_vote` per whole unit of voting power
+/// (`10^decimals`) instead. Each voter is counted once per proposal.
+pub struct GovernanceSource {
+    /// Chain the governor is deployed on.
+    pub chain_name: String,
+    /// Provider for the chain.
+    provider: ChainProvider,
+    /// Governor contract address.
+    pub address: Address,
+    /// Rewards per vote, or per whole unit of voting power if weighted.
+    pub rewards_per_vote: U256,
+    /// Whether rewards are weighted by voting power.
+    pub weighted: bool,
+    /// Voting token decimals, used when weighted.
+    pub decimals: u8,
+    /// First block votes are counted from.
+    pub from_block: u64,
+    /// Last block votes are counted to.
+    pub to_block: u64,
+    /// Number of blocks requested per `eth_getLogs` call.
+    pub chunk_size: u64,
+    /// Rewards per voter, computed once from the logs.
+    rewards: OnceCell<HashMap<Address, U256>>,
+}
+
+impl GovernanceSource {
+    pub fn new(
+        chain_name: &str,
+        provider: ChainProvider,
+        address: &str,
+        rewards_per_vote: U256,
+        from_block: u64,
+        to_block: u64,
+        chunk_size: u64,
+    ) -> Result<Self> {
+        check_block_range(from_block, to_block)?;
+        let governor = Address::from_str(address)?;
+        Ok(Self {
+            chain_name: chain_name.to_string(),
+            provider,
+            address: governor,
+            rewards_per_vote,
+            weighted: false,
+            decimals: 18,
+            from_block,
+            to_block,
+            chunk_size,
+            rewards: OnceCell::new(),
+        })
+    }
+
+    /// Weight each vote by the voting power cast, for a voting token with
+    /// `decimals` decimals.
+    pub fn with_voting_power(mut self, decimals: u8) -> Self {
+        self.weighted = true;
+        self.decimals = decimals;
+        self
+    }
+}
+
+#[async_trait(?Send)]
+impl Source for GovernanceSource {
+    fn get_name(&self) -> &str {
+        "governance votes"
+    }
+
+    async fn get_accounts(&self) -> Result<Vec<String>> {
+        let rewards = self.query_rewards().await?;
+        Ok(rewards
+            .iter()
+            .filter(|(_, rewards)| !rewards.is_zero())
+            .map(|(voter, _)| voter.to_string())
+            .collect())
+    }
+
+    async fn get_rewards(&self, account: &str) -> Result<U256> {
+        let address = Address::from_str(account)?;
+        Ok(self.query_rewards().await?.get(&address).copied().unwrap_or_default())
+    }
+
+    async fn get_rewards_for_accounts(&self, accounts: &[String]) -> Result<HashMap<String, U256>> {
+        let rewards = self.query_rewards().await?;
+        accounts
+            .iter()
+            .map(|account| {
+                let voter = Address::from_str(account)?;
+                Ok((account.clone(), rewards.get(&voter).copied().unwrap_or_default()))
+            })
+            .collect()
+    }
+
+    async fn get_metadata(&self) -> Result<serde_json::Value> {
+        Ok(serde_json::json!({
+            "chain": self.chain_name,
+            "address": self.address.to_string(),
+            "rewards_per_vote": self.rewards_per_vote.to_string(),
+            "weighted": self.weighted,
+            "decimals": self.decimals,
+            "from_block": self.from_block,
+            "to_block": self.to_block,
+        }))
+    }
+}
+
+impl GovernanceSource {
+    async fn query_rewards(&self) -> Result<&HashMap<Address, U256>> {
+        if let Some(rewards) = self.rewards.get() {
+            return Ok(rewards);
+        }
+
+        let filter = Filter::new()
+            .address(self.address)
+            .event_signature(vec![VoteCast::SIGNATURE_HASH, VoteCastWithParams::SIGNATURE_HASH]);
+        let logs = get_logs_chunked(
+            &self.provider,
+            filter,
+            self.from_block,
+            self.to_block,
+            self.chunk_size,
+        )
+        .await?;
+
+        let decimals = self.weighted.then_some(self.decimals);
+        let rewards = rewards_for_votes(&logs, self.rewards_per_vote, decimals)?;
+        Ok(self.rewards.get_or_init(|| rewards))
+    }
+}
+
+/// Check that votes are counted over a non-empty block range.
+fn check_block_range(from_block: u64, to_block: u64) -> Result<()> {
+    if from_block > to_block {
+        return Err(anyhow::anyhow!(
+            "Governance start block {} is after end block {}",
+            from_block,
+            to_block
+        ));
+    }
+    Ok(())
+}
+
+/// Rewards per voter from `VoteCast` and `VoteCastWithParams` logs, counting
+/// each voter once per proposal. Votes are weighted by voting power when
+/// `decimals` is set.
+fn rewards_for_votes(
+    logs: &[Log],
+    rewards_per_vote: U256,
+    decimals: Option<u8>,
+) -> Result<HashMap<Address, U256>> {
+    let mut votes = BTreeSet::new();
+    let mut rewards: HashMap<Address, U256> = HashMap::new();
+    for log in logs {
+        let Some(topic0) = log.topic0() else {
+            continue;
+        };
+        let (voter, proposal_id, weight) = if *topic0 == VoteCast::SIGNATURE_HASH {
+            let event = VoteCast::decode_log_data(log.data())?;
+            (event.voter, event.proposalId, event.weight)
+        } else if *topic0 == VoteCastWithParams::SIGNATURE_HASH {
+            let event = VoteCastWithParams::decode_log_data(log.data())?;
+            (event.voter, event.proposalId, event.weight)
+        } else {
+            continue;
+        };
+        if !votes.insert((voter, proposal_id)) {
+            continue;
+        }
+
+        let total = rewards.entry(voter).or_default();
+        *total = total
+            .checked_add(rewards_for_vote(rewards_per_vote, decimals, weight)?)
+            .ok_or(anyhow::anyhow!("Governance rewards overflow"))?;
+    }
+    Ok(rewards)
+}
+
+/// Rewards for a single vote cast with `weight` voting power.
+fn rewards_for_vote(rewards_per_vote: U256, decimals: Option<u8>, weight: U256) -> Result<U256> {
+    let Some(decimals) = decimals else {
+        return Ok(rewards_per_vote);
+    };
+
+    let unit = U256::from(10).pow(U256::from(decimals));
+    let rewards = weight
+        .checked_mul(rewards_per_vote)
+        .ok_or(anyhow::anyhow!("Governance rewards overflow"))?
+        / unit;
+    Ok(rewards)
+}
+
+sol! {
+    event VoteCast(address indexed voter, uint256 proposalId, uint8 support, uint256 weight, string reason);
+    event VoteCastWithParams(address indexed voter, uint256 proposalId, uint8 support, uint256 weight, string reason, bytes params);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sources::logs::tests::log;
+    use wavs_wasi_utils::evm::alloy_primitives::Bytes;
+
+    const GOVERNOR: Address = Address::repeat_byte(0x90);
+    const A: Address = Address::repeat_byte(0xaa);
+    const B: Address = Address::repeat_byte(0xbb);
+
+    fn vote(voter: Address, proposal_id: u64, weight: U256) -> Log {
+        let event = VoteCast {
+            voter,
+            proposalId: U256::from(proposal_id),
+            support: 1,
+            weight,
+            reason: String::new(),
+        };
+        log(GOVERNOR, event.encode_log_data())
+    }
+
+    fn vote_with_params(voter: Address, proposal_id: u64, weight: U256) -> Log {
+        let event = VoteCastWithParams {
+            voter,
+            proposalId: U256::from(proposal_id),
+            support: 1,
+            weight,
+            reason: String::new(),
+            params: Bytes::from_static(&[1]),
+        };
+        log(GOVERNOR, event.encode_log_data())
+    }
+
+    fn ether(amount: u64) -> U256 {
+        U256::from(amount) * U256::from(10).pow(U256::from(18))
+    }
+
+    fn paid(rewards: &HashMap<Address, U256>, voter: Address) -> U256 {
+        rewards.get(&voter).copied().unwrap_or_default()
+    }
+
+    #[test]
+    fn flat_votes_pay_per_vote() {
+        let logs = [vote(A, 1, ether(5)), vote(A, 2, ether(1)), vote(B, 1, ether(100))];
+        let rewards = rewards_for_votes(&logs, U256::from(10), None).unwrap();
+        assert_eq!(paid(&rewards, A), U256::from(20));
+        assert_eq!(paid(&rewards, B), U256::from(10));
+    }
+
+    #[test]
+    fn weighted_votes_pay_per_whole_unit() {
+        let logs = [vote(A, 1, ether(5)), vote(B, 1, U256::from(1_500_000))];
+        let weighted = rewards_for_votes(&logs, U256::from(10), Some(18)).unwrap();
+        assert_eq!(paid(&weighted, A), U256::from(50));
+        // Less than one whole unit rounds down.
+        assert_eq!(paid(&weighted, B), U256::ZERO);
+
+        let six_decimals = rewards_for_votes(&logs, U256::from(10), Some(6)).unwrap();
+        assert_eq!(paid(&six_decimals, B), U256::from(15));
+    }
+
+    #[test]
+    fn each_voter_counts_once_per_proposal() {
+        let logs = [
+            vote(A, 1, ether(5)),
+            vote_with_params(A, 1, ether(5)),
+            vote_with_params(A, 2, ether(3)),
+            vote(A, 2, ether(3)),
+            vote_with_params(B, 1, ether(2)),
+        ];
+        let rewards = rewards_for_votes(&logs, U256::from(10), Some(18)).unwrap();
+        assert_eq!(paid(&rewards, A), U256::from(80));
+        assert_eq!(paid(&rewards, B), U256::from(20));
+    }
+
+    #[test]
+    fn reversed_block_range_is_rejected() {
+        assert!(check_block_range(100, 99).is_err());
+        assert!(check_block_range(100, 100).is_ok());
+    }
+}
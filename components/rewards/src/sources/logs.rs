@@ -0,0 +1,303 @@
+use alloy_rpc_types::{Filter, Log};
+use alloy_sol_types::{sol, SolEvent};
+use anyhow::Result;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use wavs_wasi_utils::evm::alloy_primitives::{Address, B256, U256};
+
+use crate::provider::ChainProvider;
+
+/// Default number of blocks requested per `eth_getLogs` call.
+pub const DEFAULT_LOGS_CHUNK_SIZE: u64 = 10_000;
+
+/// Most token IDs matched by topic in one `eth_getLogs` filter, since
+/// providers cap the number of topics in a filter.
+const TOKEN_IDS_PER_FILTER: usize = 100;
+
+/// How a source discovers the accounts holding its token.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum HolderDiscovery {
+    /// Call the custom `getAllHolders()` view on the contract.
+    #[default]
+    GetAllHolders,
+    /// Rebuild current holders by replaying `Transfer` logs over a block range.
+    ///
+    /// `from_block` should be at or before the contract deployment so that
+    /// balances are reconstructed from the first mint. When `to_block` is not
+    /// set, the snapshot block is used.
+    TransferLogs {
+        from_block: u64,
+        #[serde(default)]
+        to_block: Option<u64>,
+        #[serde(default = "default_chunk_size")]
+        chunk_size: u64,
+    },
+}
+
+impl HolderDiscovery {
+    /// Discover holders from `Transfer` logs starting at `from_block`.
+    pub fn transfer_logs(from_block: u64) -> Self {
+        Self::TransferLogs { from_block, to_block: None, chunk_size: DEFAULT_LOGS_CHUNK_SIZE }
+    }
+
+    /// Get metadata about the discovery mode.
+    pub fn get_metadata(&self) -> serde_json::Value {
+        match self {
+            Self::GetAllHolders => serde_json::json!({ "type": "get_all_holders" }),
+            Self::TransferLogs { from_block, to_block, chunk_size } => serde_json::json!({
+                "type": "transfer_logs",
+                "from_block": from_block,
+                "to_block": to_block,
+                "chunk_size": chunk_size,
+            }),
+        }
+    }
+}
+
+/// Default `chunk_size` for config fields paging through logs.
+pub fn default_chunk_size() -> u64 {
+    DEFAULT_LOGS_CHUNK_SIZE
+}
+
+/// Fetch all logs matching `filter` between two blocks (inclusive), paging
+/// through the range `chunk_size` blocks at a time.
+pub async fn get_logs_chunked(
+    provider: &ChainProvider,
+    filter: Filter,
+    from_block: u64,
+    to_block: u64,
+    chunk_size: u64,
+) -> Result<Vec<Log>> {
+    if chunk_size == 0 {
+        return Err(anyhow::anyhow!("Logs chunk size must be greater than zero"));
+    }
+
+    let mut logs = Vec::new();
+    let mut start = from_block;
+    while start <= to_block {
+        let end = start.saturating_add(chunk_size - 1).min(to_block);
+        let chunk_filter = filter.clone().from_block(start).to_block(end);
+        logs.extend(provider.get_logs(&chunk_filter).await?);
+        start = end + 1;
+    }
+
+    Ok(logs)
+}
+
+/// Rebuild the current holders of an ERC20 or ERC721 contract from its
+/// `Transfer` logs.
+///
+/// ERC721 transfers index the token ID as a fourth topic and are tracked by
+/// token ownership. ERC20 transfers carry the value in the log data and are
+/// tracked by running balance. Only accounts with a non-zero holding at
+/// `to_block` are returned.
+pub async fn query_transfer_holders(
+    provider: &ChainProvider,
+    address: Address,
+    from_block: u64,
+    to_block: u64,
+    chunk_size: u64,
+) -> Result<Vec<Address>> {
+    let filter = Filter::new().address(address).event_signature(Transfer::SIGNATURE_HASH);
+    let logs = get_logs_chunked(provider, filter, from_block, to_block, chunk_size).await?;
+
+    Ok(replay_transfer_holders(&logs))
+}
+
+/// Apply ERC20 or ERC721 `Transfer` logs, in order, and return the accounts
+/// left with a non-zero holding. Balances saturate at zero, so logs starting
+/// after the first mint don't underflow.
+fn replay_transfer_holders(logs: &[Log]) -> Vec<Address> {
+    let mut owners = HashMap::new();
+    replay_token_transfers(&mut owners, logs);
+
+    // ERC20: Transfer(address indexed, address indexed, uint256)
+    let mut balances: HashMap<Address, U256> = HashMap::new();
+    for log in logs.iter().filter(|log| log.topics().len() == 3) {
+        let topics = log.topics();
+        let from = topic_to_address(&topics[1]);
+        let to = topic_to_address(&topics[2]);
+        let value = U256::from_be_slice(&log.data().data);
+        if from != Address::ZERO {
+            let balance = balances.entry(from).or_default();
+            *balance = balance.saturating_sub(value);
+        }
+        if to != Address::ZERO {
+            let balance = balances.entry(to).or_default();
+            *balance = balance.saturating_add(value);
+        }
+    }
+
+    for owner in owners.into_values() {
+        let balance = balances.entry(owner).or_default();
+        *balance = balance.saturating_add(U256::from(1));
+    }
+
+    balances
+        .into_iter()
+        .filter(|(_, balance)| !balance.is_zero())
+        .map(|(holder, _)| holder)
+        .collect()
+}
+
+/// Rebuild the current owner of each token ID of an ERC721 contract from its
+/// `Transfer` logs. Burned tokens are left out.
+pub async fn query_token_owners(
+    provider: &ChainProvider,
+    address: Address,
+    from_block: u64,
+    to_block: u64,
+    chunk_size: u64,
+) -> Result<HashMap<U256, Address>> {
+    let filter = Filter::new().address(address).event_signature(Transfer::SIGNATURE_HASH);
+    let logs = get_logs_chunked(provider, filter, from_block, to_block, chunk_size).await?;
+
+    let mut owners = HashMap::new();
+    replay_token_transfers(&mut owners, &logs);
+    Ok(owners)
+}
+
+/// Like [`query_token_owners`], but only for `token_ids`.
+///
+/// The range is scanned once either way: up to 100 token IDs are matched by
+/// topic in the filter, and larger sets replay every `Transfer` of the
+/// contract and keep the requested token IDs.
+pub async fn query_owners_of_tokens(
+    provider: &ChainProvider,
+    address: Address,
+    token_ids: &[U256],
+    from_block: u64,
+    to_block: u64,
+    chunk_size: u64,
+) -> Result<HashMap<U256, Address>> {
+    if token_ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+    if token_ids.len() > TOKEN_IDS_PER_FILTER {
+        let wanted = token_ids.iter().collect::<HashSet<_>>();
+        let mut owners =
+            query_token_owners(provider, address, from_block, to_block, chunk_size).await?;
+        owners.retain(|token_id, _| wanted.contains(token_id));
+        return Ok(owners);
+    }
+
+    let topics = token_ids.iter().map(|token_id| B256::from(*token_id)).collect::<Vec<_>>();
+    let filter =
+        Filter::new().address(address).event_signature(Transfer::SIGNATURE_HASH).topic3(topics);
+    let logs = get_logs_chunked(provider, filter, from_block, to_block, chunk_size).await?;
+
+    let mut owners = HashMap::new();
+    replay_token_transfers(&mut owners, &logs);
+    Ok(owners)
+}
+
+/// Apply ERC721 `Transfer` logs, in order, to the owner of each token ID.
+/// Transfers without a token ID topic are skipped.
+fn replay_token_transfers(owners: &mut HashMap<U256, Address>, logs: &[Log]) {
+    for log in logs {
+        let topics = log.topics();
+        if topics.len() != 4 {
+            continue;
+        }
+        let to = topic_to_address(&topics[2]);
+        let token_id = U256::from_be_bytes(topics[3].0);
+        if to == Address::ZERO {
+            owners.remove(&token_id);
+        } else {
+            owners.insert(token_id, to);
+        }
+    }
+}
+
+/// Extract an address from an indexed event topic.
+pub fn topic_to_address(topic: &B256) -> Address {
+    Address::from_word(*topic)
+}
+
+sol! {
+    event Transfer(address indexed from, address indexed to, uint256 value);
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+    use wavs_wasi_utils::evm::alloy_primitives::LogData;
+
+    const TOKEN: Address = Address::repeat_byte(0x11);
+    const A: Address = Address::repeat_byte(0xaa);
+    const B: Address = Address::repeat_byte(0xbb);
+    const C: Address = Address::repeat_byte(0xcc);
+
+    /// An RPC log emitted by `address`, without block or transaction details.
+    pub(crate) fn log(address: Address, data: LogData) -> Log {
+        Log {
+            inner: wavs_wasi_utils::evm::alloy_primitives::Log { address, data },
+            ..Default::default()
+        }
+    }
+
+    fn erc20_transfer(from: Address, to: Address, value: u64) -> Log {
+        log(TOKEN, Transfer { from, to, value: U256::from(value) }.encode_log_data())
+    }
+
+    fn erc721_transfer(from: Address, to: Address, token_id: u64) -> Log {
+        let data = erc721::Transfer { from, to, tokenId: U256::from(token_id) }.encode_log_data();
+        log(TOKEN, data)
+    }
+
+    fn holders(logs: &[Log]) -> BTreeSet<Address> {
+        replay_transfer_holders(logs).into_iter().collect()
+    }
+
+    #[test]
+    fn erc20_mint_transfer_and_burn() {
+        let logs = [
+            erc20_transfer(Address::ZERO, A, 100),
+            erc20_transfer(A, B, 40),
+            erc20_transfer(Address::ZERO, C, 5),
+            erc20_transfer(C, Address::ZERO, 5),
+        ];
+        assert_eq!(holders(&logs), BTreeSet::from([A, B]));
+
+        let logs = [erc20_transfer(Address::ZERO, A, 100), erc20_transfer(A, B, 100)];
+        assert_eq!(holders(&logs), BTreeSet::from([B]));
+    }
+
+    #[test]
+    fn erc20_balances_saturate_when_logs_start_after_deployment() {
+        // A's mint happened before the first replayed block.
+        let logs = [erc20_transfer(A, B, 40), erc20_transfer(B, C, 10)];
+        assert_eq!(holders(&logs), BTreeSet::from([B, C]));
+    }
+
+    #[test]
+    fn erc721_mint_transfer_and_burn() {
+        let logs = [
+            erc721_transfer(Address::ZERO, A, 1),
+            erc721_transfer(Address::ZERO, A, 2),
+            erc721_transfer(A, B, 1),
+            erc721_transfer(Address::ZERO, C, 3),
+            erc721_transfer(C, Address::ZERO, 3),
+        ];
+        assert_eq!(holders(&logs), BTreeSet::from([A, B]));
+
+        let mut owners = HashMap::new();
+        replay_token_transfers(&mut owners, &logs);
+        assert_eq!(owners, HashMap::from([(U256::from(1), B), (U256::from(2), A)]));
+    }
+
+    #[test]
+    fn erc721_transfers_after_deployment_track_current_owner() {
+        // Token 7 was minted before the first replayed block.
+        let logs = [erc721_transfer(A, B, 7), erc721_transfer(B, C, 7)];
+        assert_eq!(holders(&logs), BTreeSet::from([C]));
+    }
+
+    mod erc721 {
+        alloy_sol_types::sol! {
+            event Transfer(address indexed from, address indexed to, uint256 indexed tokenId);
+        }
+    }
+}
@@ -1,10 +1,29 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::str::FromStr;
 
 use anyhow::Result;
 use async_trait::async_trait;
-use wavs_wasi_utils::evm::alloy_primitives::U256;
+use serde::{Deserialize, Serialize};
+use wavs_wasi_utils::evm::alloy_primitives::{Address, U256};
+use wit_bindgen_rt::async_support::futures;
 
+use crate::config::{SourceSpec, DEFAULT_WEIGHT_BPS};
+use crate::policy::{AccountFilter, RewardPolicy};
+use crate::provider::ProviderCache;
+
+pub mod contract_accounts;
+pub mod erc1155;
+pub mod erc20;
 pub mod erc721;
+pub mod events;
+pub mod governance;
+pub mod logs;
+pub mod multicall;
+pub mod multipliers;
+pub mod pool;
+pub mod staking;
+pub mod time_weighted;
+pub mod uniswap_v3;
 
 /// A source of rewards.
 #[async_trait(?Send)]
@@ -18,57 +37,248 @@ pub trait Source {
     /// Get the rewards for an account.
     async fn get_rewards(&self, account: &str) -> Result<U256>;
 
+    /// Get the rewards for many accounts at once, keyed by account.
+    ///
+    /// Defaults to calling `get_rewards` for each account. Sources that can
+    /// compute every account's rewards in one pass should override this.
+    async fn get_rewards_for_accounts(&self, accounts: &[String]) -> Result<HashMap<String, U256>> {
+        let rewards =
+            futures::future::join_all(accounts.iter().map(|account| self.get_rewards(account)))
+                .await;
+        accounts
+            .iter()
+            .zip(rewards)
+            .map(|(account, rewards)| Ok((account.clone(), rewards?)))
+            .collect()
+    }
+
     /// Get metadata about the source.
     async fn get_metadata(&self) -> Result<serde_json::Value>;
 }
 
+/// One source's contribution to an account's rewards in a reward token.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SourceReward {
+    /// Name of the source.
+    pub source: String,
+    /// Position of the source in the registry.
+    pub index: usize,
+    /// Weighted amount the source added, as a big number string.
+    pub amount: String,
+}
+
+/// An account's rewards in one reward token.
+#[derive(Default)]
+pub struct TokenRewards {
+    /// Total across all sources paying the token.
+    pub total: U256,
+    /// Amount each source added, skipping sources that added nothing. These
+    /// are recorded before the reward policy, so they may not sum to `total`.
+    pub sources: Vec<SourceReward>,
+}
+
+/// A source in the registry, along with the weight applied to its rewards and
+/// the token it pays them in.
+struct RegisteredSource {
+    source: Box<dyn Source>,
+    /// Weight in basis points (10000 = 1x).
+    weight: u64,
+    /// Reward token address.
+    reward_token: Address,
+}
+
 /// A registry that manages multiple reward sources.
 pub struct SourceRegistry {
-    sources: Vec<Box<dyn Source>>,
+    sources: Vec<RegisteredSource>,
+    /// The block each chain's sources are queried at.
+    snapshot_blocks: BTreeMap<String, u64>,
+    /// Per-account limits applied after aggregation.
+    policy: RewardPolicy,
+    /// Accounts allowed into the distribution.
+    filter: AccountFilter,
 }
 
 impl SourceRegistry {
     /// Create a new empty registry.
     pub fn new() -> Self {
-        Self { sources: Vec::new() }
+        Self {
+            sources: Vec::new(),
+            snapshot_blocks: BTreeMap::new(),
+            policy: RewardPolicy::default(),
+            filter: AccountFilter::default(),
+        }
+    }
+
+    /// Only reward accounts allowed by `filter`.
+    pub fn with_filter(mut self, filter: AccountFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Apply per-account limits to the aggregated rewards.
+    pub fn with_policy(mut self, policy: RewardPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Create a registry from a list of source specs.
+    ///
+    /// Sources may live on different chains; each gets the shared provider for
+    /// its chain from `providers`. Every query is pinned to the chain's
+    /// block in `snapshot_blocks`. A chain without one is an error rather than
+    /// the latest block, since operators would each see a different block.
+    ///
+    /// Sources without a reward token of their own pay `default_reward_token`.
+    pub async fn from_specs(
+        specs: &[SourceSpec],
+        providers: &ProviderCache,
+        snapshot_blocks: BTreeMap<String, u64>,
+        default_reward_token: Address,
+    ) -> Result<Self> {
+        let mut registry = Self { snapshot_blocks, ..Self::new() };
+        for spec in specs {
+            let provider = providers.get(&spec.chain)?;
+            let block = *registry.snapshot_blocks.get(&spec.chain).ok_or(anyhow::anyhow!(
+                "No snapshot block for chain {}; set it in the snapshot_blocks config",
+                spec.chain
+            ))?;
+            let reward_token = match &spec.reward_token {
+                Some(reward_token) => Address::from_str(reward_token)?,
+                None => default_reward_token,
+            };
+            registry.add_source(spec.build(provider, block)?, spec.weight, reward_token);
+        }
+        Ok(registry)
+    }
+
+    /// Get the block each chain's sources are queried at.
+    pub fn get_snapshot_blocks(&self) -> &BTreeMap<String, u64> {
+        &self.snapshot_blocks
+    }
+
+    /// Get the per-account limits applied after aggregation.
+    pub fn get_policy(&self) -> &RewardPolicy {
+        &self.policy
+    }
+
+    /// Get the accounts allowed into the distribution.
+    pub fn get_filter(&self) -> &AccountFilter {
+        &self.filter
     }
 
-    /// Add a new source to the registry.
-    pub fn add_source<S: Source + 'static>(&mut self, source: S) {
-        self.sources.push(Box::new(source));
+    /// Add a new source to the registry with a weight in basis points, paying
+    /// rewards in `reward_token`.
+    pub fn add_source(&mut self, source: Box<dyn Source>, weight: u64, reward_token: Address) {
+        self.sources.push(RegisteredSource { source, weight, reward_token });
     }
 
     /// Get aggregated accounts from all sources (deduplicated).
+    ///
+    /// Accounts are normalized to checksum addresses and sorted by address, so
+    /// the same holders always produce the same output. Accounts rejected by
+    /// the account filter are left out.
     pub async fn get_accounts(&self) -> Result<Vec<String>> {
-        let mut accounts = HashSet::new();
-        for source in &self.sources {
-            accounts.extend(source.get_accounts().await?);
+        let mut accounts = BTreeSet::new();
+        for RegisteredSource { source, .. } in &self.sources {
+            for account in source.get_accounts().await? {
+                let account = Address::from_str(&account)?;
+                if self.filter.allows(&account) {
+                    accounts.insert(account);
+                }
+            }
         }
-        Ok(accounts.into_iter().collect())
+        Ok(accounts.into_iter().map(|account| account.to_string()).collect())
     }
 
-    /// Get rewards for an account across all sources.
-    pub async fn get_rewards(&self, account: &str) -> Result<U256> {
-        let mut total = U256::ZERO;
+    /// Get rewards for many accounts across all sources, totalled per reward
+    /// token and keyed by account, along with the amount each source added.
+    ///
+    /// Each source computes its rewards for every account in one bulk call.
+    pub async fn get_rewards_for_accounts(
+        &self,
+        accounts: &[String],
+    ) -> Result<BTreeMap<Address, BTreeMap<Address, TokenRewards>>> {
+        let mut totals: BTreeMap<Address, BTreeMap<Address, TokenRewards>> = BTreeMap::new();
+
+        for (index, RegisteredSource { source, weight, reward_token }) in
+            self.sources.iter().enumerate()
+        {
+            let source_rewards = source.get_rewards_for_accounts(accounts).await?;
+            for account in accounts {
+                let weighted_rewards = source_rewards
+                    .get(account)
+                    .copied()
+                    .unwrap_or_default()
+                    .checked_mul(U256::from(*weight))
+                    .ok_or(anyhow::anyhow!("Weighted rewards overflow"))?
+                    / U256::from(DEFAULT_WEIGHT_BPS);
+                if weighted_rewards.is_zero() {
+                    continue;
+                }
+
+                let rewards = totals
+                    .entry(Address::from_str(account)?)
+                    .or_default()
+                    .entry(*reward_token)
+                    .or_default();
+                rewards.total = rewards
+                    .total
+                    .checked_add(weighted_rewards)
+                    .ok_or(anyhow::anyhow!("Total rewards overflow"))?;
+                rewards.sources.push(SourceReward {
+                    source: source.get_name().to_string(),
+                    index,
+                    amount: weighted_rewards.to_string(),
+                });
+            }
+        }
+
+        Ok(totals)
+    }
+
+    /// Apply the reward policy to aggregated rewards, keyed by account then
+    /// reward token.
+    ///
+    /// `carried_over` holds amounts kept out of earlier trees, keyed by
+    /// (account, reward token); they are added back before the policy is
+    /// applied, unless the account filter now rejects the account. Returns the
+    /// amounts to carry over to the next epoch.
+    pub fn apply_policy(
+        &self,
+        rewards: &mut BTreeMap<Address, BTreeMap<Address, TokenRewards>>,
+        carried_over: &BTreeMap<(Address, Address), U256>,
+    ) -> Result<BTreeMap<(Address, Address), U256>> {
+        for (account, token) in
+            carried_over.keys().filter(|(account, _)| self.filter.allows(account))
+        {
+            rewards.entry(*account).or_default().entry(*token).or_default();
+        }
 
-        for source in &self.sources {
-            let source_rewards = source.get_rewards(account).await?;
-            total = total
-                .checked_add(source_rewards)
-                .ok_or(anyhow::anyhow!("Total rewards overflow"))?;
+        let mut carry = BTreeMap::new();
+        for (account, tokens) in rewards.iter_mut() {
+            for (token, token_rewards) in tokens.iter_mut() {
+                let carried = carried_over.get(&(*account, *token)).copied().unwrap_or_default();
+                let (paid, carried) = self.policy.apply(token_rewards.total, carried)?;
+                token_rewards.total = paid;
+                if !carried.is_zero() {
+                    carry.insert((*account, *token), carried);
+                }
+            }
         }
 
-        Ok(total)
+        Ok(carry)
     }
 
     /// Get metadata about all sources.
     pub async fn get_sources_with_metadata(&self) -> Result<Vec<serde_json::Value>> {
         let mut metadata = Vec::new();
-        for source in &self.sources {
+        for RegisteredSource { source, weight, reward_token } in &self.sources {
             let name = source.get_name();
             let source_metadata = source.get_metadata().await?;
             metadata.push(serde_json::json!({
                 "name": name,
+                "weight": weight,
+                "reward_token": reward_token.to_string(),
                 "metadata": source_metadata,
             }));
         }
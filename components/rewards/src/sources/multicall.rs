@@ -0,0 +1,287 @@
+use alloy_rpc_types::TransactionInput;
+use alloy_sol_types::{sol, SolCall};
+use anyhow::Result;
+use std::future::Future;
+use wavs_wasi_utils::evm::alloy_primitives::{address, Address, Bytes, TxKind, U256};
+
+use crate::provider::ChainProvider;
+
+/// Multicall3 is deployed at the same address on most EVM chains.
+pub const MULTICALL3_ADDRESS: Address = address!("cA11bde05977b3631167028862bE2a173976CA11");
+
+/// Default number of calls batched into one `aggregate3` call.
+pub const DEFAULT_MULTICALL_CHUNK_SIZE: usize = 500;
+
+/// Query `balanceOf(owner)` on `token` for a single owner.
+pub async fn balance_of(
+    provider: &ChainProvider,
+    block: u64,
+    token: Address,
+    owner: Address,
+) -> Result<U256> {
+    let balance_call = IBalanceOf::balanceOfCall { owner };
+    let result = call(provider, block, token, balance_call.abi_encode().into()).await?;
+    Ok(U256::from_be_slice(&result))
+}
+
+/// Query `balanceOf(owner)` on `token` for many owners, batched through
+/// Multicall3 `aggregate3` in chunks of `chunk_size`.
+///
+/// Balances are returned in the same order as `owners`. See [`batch_call`]
+/// for how batching falls back to single calls.
+pub async fn batch_balance_of(
+    provider: &ChainProvider,
+    block: u64,
+    token: Address,
+    owners: &[Address],
+    chunk_size: usize,
+) -> Result<Vec<U256>> {
+    let calls = owners
+        .iter()
+        .map(|owner| (token, IBalanceOf::balanceOfCall { owner: *owner }.abi_encode().into()))
+        .collect::<Vec<_>>();
+    let results = batch_call(provider, block, &calls, chunk_size).await?;
+    Ok(results.into_iter().map(|result| U256::from_be_slice(&result)).collect())
+}
+
+/// Execute many `eth_call`s, given as (target, calldata), batched through
+/// Multicall3 `aggregate3` in chunks of `chunk_size`.
+///
+/// If a batch fails, for example because Multicall3 is not deployed on the
+/// chain, its calls are made one by one instead. A `chunk_size` of zero
+/// disables batching. Results are returned in the same order as `calls`.
+pub async fn batch_call(
+    provider: &ChainProvider,
+    block: u64,
+    calls: &[(Address, Bytes)],
+    chunk_size: usize,
+) -> Result<Vec<Bytes>> {
+    batch_call_with(calls, chunk_size, |to, input| call(provider, block, to, input)).await
+}
+
+/// [`batch_call`], making each `eth_call` through `call`.
+async fn batch_call_with<F, Fut>(
+    calls: &[(Address, Bytes)],
+    chunk_size: usize,
+    call: F,
+) -> Result<Vec<Bytes>>
+where
+    F: Fn(Address, Bytes) -> Fut,
+    Fut: Future<Output = Result<Bytes>>,
+{
+    let mut results = Vec::with_capacity(calls.len());
+    if chunk_size == 0 {
+        for (target, input) in calls {
+            results.push(call(*target, input.clone()).await?);
+        }
+        return Ok(results);
+    }
+
+    for chunk in calls.chunks(chunk_size) {
+        let aggregated = call(MULTICALL3_ADDRESS, encode_aggregate3(chunk))
+            .await
+            .and_then(|result| decode_aggregate3(&result, chunk.len()));
+        match aggregated {
+            Ok(chunk_results) => results.extend(chunk_results),
+            Err(e) => {
+                eprintln!("Multicall3 batch failed, falling back to single calls: {}", e);
+                for (target, input) in chunk {
+                    results.push(call(*target, input.clone()).await?);
+                }
+            }
+        }
+    }
+
+    Ok(results)
+}
+
+/// Encode an `aggregate3` call that reverts if any of `calls` fails.
+fn encode_aggregate3(calls: &[(Address, Bytes)]) -> Bytes {
+    let call3s = calls
+        .iter()
+        .map(|(target, input)| IMulticall3::Call3 {
+            target: *target,
+            allowFailure: false,
+            callData: input.clone(),
+        })
+        .collect();
+    IMulticall3::aggregate3Call { calls: call3s }.abi_encode().into()
+}
+
+/// Decode the return data of an `aggregate3` call made for `expected` calls.
+fn decode_aggregate3(result: &[u8], expected: usize) -> Result<Vec<Bytes>> {
+    let results = IMulticall3::aggregate3Call::abi_decode_returns(result)?;
+    if results.len() != expected {
+        return Err(anyhow::anyhow!(
+            "Multicall3 returned {} results for {} calls",
+            results.len(),
+            expected
+        ));
+    }
+    if let Some(index) = results.iter().position(|result| !result.success) {
+        return Err(anyhow::anyhow!("Multicall3 call {} failed", index));
+    }
+
+    Ok(results.into_iter().map(|result| result.returnData).collect())
+}
+
+/// Execute `eth_call` on `to` at a block.
+pub async fn call(
+    provider: &ChainProvider,
+    block: u64,
+    to: Address,
+    input: Bytes,
+) -> Result<Bytes> {
+    let tx = alloy_rpc_types::eth::TransactionRequest {
+        to: Some(TxKind::Call(to)),
+        input: TransactionInput { input: Some(input), data: None },
+        ..Default::default()
+    };
+
+    provider.call(tx, block).await
+}
+
+sol! {
+    interface IBalanceOf {
+        function balanceOf(address owner) external view returns (uint256);
+    }
+    interface IMulticall3 {
+        struct Call3 {
+            address target;
+            bool allowFailure;
+            bytes callData;
+        }
+        struct Call3Result {
+            bool success;
+            bytes returnData;
+        }
+        function aggregate3(Call3[] calldata calls) external payable returns (Call3Result[] memory returnData);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use std::{
+        cell::Cell,
+        future::{ready, Ready},
+    };
+
+    const TOKEN: Address = Address::repeat_byte(0x11);
+
+    fn balance_calls(count: u8) -> Vec<(Address, Bytes)> {
+        (0..count)
+            .map(|i| {
+                let owner = Address::repeat_byte(i);
+                (TOKEN, IBalanceOf::balanceOfCall { owner }.abi_encode().into())
+            })
+            .collect()
+    }
+
+    /// A chain where every call returns its own calldata, and Multicall3 runs
+    /// its calls that way if `multicall` is deployed.
+    fn echo<'a>(
+        multicall: bool,
+        aggregates: &'a Cell<usize>,
+        singles: &'a Cell<usize>,
+    ) -> impl Fn(Address, Bytes) -> Ready<Result<Bytes>> + 'a {
+        move |to, input| {
+            if to != MULTICALL3_ADDRESS {
+                singles.set(singles.get() + 1);
+                return ready(Ok(input));
+            }
+            aggregates.set(aggregates.get() + 1);
+            if !multicall {
+                return ready(Err(anyhow::anyhow!("execution reverted")));
+            }
+            let calls = IMulticall3::aggregate3Call::abi_decode(&input).unwrap().calls;
+            let results = calls
+                .into_iter()
+                .map(|call| IMulticall3::Call3Result { success: true, returnData: call.callData })
+                .collect::<Vec<_>>();
+            ready(Ok(IMulticall3::aggregate3Call::abi_encode_returns(&results).into()))
+        }
+    }
+
+    #[test]
+    fn encode_aggregate3_disallows_failure() {
+        let calls = balance_calls(2);
+        let decoded =
+            IMulticall3::aggregate3Call::abi_decode(&encode_aggregate3(&calls)).unwrap().calls;
+
+        assert_eq!(decoded.len(), 2);
+        for (call3, (target, input)) in decoded.iter().zip(&calls) {
+            assert_eq!(call3.target, *target);
+            assert!(!call3.allowFailure);
+            assert_eq!(call3.callData, *input);
+        }
+    }
+
+    #[test]
+    fn decode_aggregate3_keeps_order() {
+        let results = vec![
+            IMulticall3::Call3Result { success: true, returnData: Bytes::from(vec![1]) },
+            IMulticall3::Call3Result { success: true, returnData: Bytes::from(vec![2]) },
+        ];
+        let encoded = IMulticall3::aggregate3Call::abi_encode_returns(&results);
+
+        let decoded = decode_aggregate3(&encoded, 2).unwrap();
+        assert_eq!(decoded, vec![Bytes::from(vec![1]), Bytes::from(vec![2])]);
+    }
+
+    #[test]
+    fn decode_aggregate3_rejects_wrong_count_and_failures() {
+        let ok = IMulticall3::Call3Result { success: true, returnData: Bytes::new() };
+        let failed = IMulticall3::Call3Result { success: false, returnData: Bytes::new() };
+
+        let encoded = IMulticall3::aggregate3Call::abi_encode_returns(&vec![ok.clone()]);
+        assert!(decode_aggregate3(&encoded, 2).is_err());
+
+        let encoded = IMulticall3::aggregate3Call::abi_encode_returns(&vec![ok, failed]);
+        assert!(decode_aggregate3(&encoded, 2).is_err());
+
+        assert!(decode_aggregate3(&[0u8; 3], 1).is_err());
+    }
+
+    #[test]
+    fn batch_call_chunks_through_multicall() {
+        let (aggregates, singles) = (Cell::new(0), Cell::new(0));
+        let calls = balance_calls(5);
+
+        let results =
+            block_on(batch_call_with(&calls, 2, echo(true, &aggregates, &singles))).unwrap();
+
+        let inputs = calls.into_iter().map(|(_, input)| input).collect::<Vec<_>>();
+        assert_eq!(results, inputs);
+        assert_eq!(aggregates.get(), 3);
+        assert_eq!(singles.get(), 0);
+    }
+
+    #[test]
+    fn batch_call_falls_back_to_single_calls() {
+        let (aggregates, singles) = (Cell::new(0), Cell::new(0));
+        let calls = balance_calls(5);
+
+        let results =
+            block_on(batch_call_with(&calls, 2, echo(false, &aggregates, &singles))).unwrap();
+
+        let inputs = calls.into_iter().map(|(_, input)| input).collect::<Vec<_>>();
+        assert_eq!(results, inputs);
+        assert_eq!(aggregates.get(), 3);
+        assert_eq!(singles.get(), 5);
+    }
+
+    #[test]
+    fn batch_call_without_batching() {
+        let (aggregates, singles) = (Cell::new(0), Cell::new(0));
+        let calls = balance_calls(3);
+
+        let results =
+            block_on(batch_call_with(&calls, 0, echo(true, &aggregates, &singles))).unwrap();
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(aggregates.get(), 0);
+        assert_eq!(singles.get(), 3);
+    }
+}
@@ -0,0 +1,270 @@
+use anyhow::Result;
+use serde::Deserialize;
+use std::{collections::BTreeMap, str::FromStr};
+use wavs_wasi_utils::evm::alloy_primitives::U256;
+
+use crate::config::DEFAULT_WEIGHT_BPS;
+
+use super::logs::default_chunk_size;
+
+/// Per-token-ID reward multipliers for an ERC721 collection, in basis points
+/// (10000 = 1x).
+///
+/// A token's multiplier comes from its trait if one is set, then from the
+/// range containing its ID, then `default_multiplier`. Token IDs are parsed
+/// and ranges checked for overlaps when the config is loaded.
+///
+/// Example:
+///
+/// ```json
+/// {
+///   "enumeration": { "type": "token_of_owner_by_index" },
+///   "ranges": [{ "from": "1", "to": "100", "multiplier": 30000 }],
+///   "traits": { "101": "legendary" },
+///   "trait_multipliers": { "legendary": 50000 }
+/// }
+/// ```
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "TokenMultipliersConfig")]
+pub struct TokenMultipliers {
+    /// How the token IDs each holder owns are enumerated.
+    pub enumeration: TokenEnumeration,
+    /// Inclusive token ID ranges as `(from, to, multiplier)`, sorted by `from`
+    /// and non-overlapping.
+    pub ranges: Vec<(U256, U256, u64)>,
+    /// Trait (metadata attribute value) of each token, by token ID.
+    pub traits: BTreeMap<U256, String>,
+    /// Multiplier for each trait.
+    pub trait_multipliers: BTreeMap<String, u64>,
+    /// Multiplier for tokens without a trait or range.
+    pub default_multiplier: u64,
+}
+
+/// The `multipliers` of an ERC721 source spec, before token IDs are parsed.
+#[derive(Deserialize)]
+struct TokenMultipliersConfig {
+    enumeration: TokenEnumeration,
+    #[serde(default)]
+    ranges: Vec<TokenIdRange>,
+    /// Keyed by decimal or 0x-prefixed token ID.
+    #[serde(default)]
+    traits: BTreeMap<String, String>,
+    #[serde(default)]
+    trait_multipliers: BTreeMap<String, u64>,
+    #[serde(default = "default_multiplier")]
+    default_multiplier: u64,
+}
+
+/// A multiplier applied to an inclusive range of token IDs.
+#[derive(Deserialize)]
+struct TokenIdRange {
+    /// First token ID, as a decimal or 0x-prefixed string.
+    from: String,
+    /// Last token ID, as a decimal or 0x-prefixed string.
+    to: String,
+    multiplier: u64,
+}
+
+impl TryFrom<TokenMultipliersConfig> for TokenMultipliers {
+    type Error = anyhow::Error;
+
+    fn try_from(config: TokenMultipliersConfig) -> Result<Self> {
+        let mut ranges = config
+            .ranges
+            .iter()
+            .map(|range| {
+                let from = parse_token_id(&range.from)?;
+                let to = parse_token_id(&range.to)?;
+                if from > to {
+                    return Err(anyhow::anyhow!("Token ID range {}-{} is empty", from, to));
+                }
+                Ok((from, to, range.multiplier))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        ranges.sort();
+        for pair in ranges.windows(2) {
+            let ((from, to, _), (next_from, next_to, _)) = (pair[0], pair[1]);
+            if next_from <= to {
+                return Err(anyhow::anyhow!(
+                    "Token ID ranges {}-{} and {}-{} overlap",
+                    from,
+                    to,
+                    next_from,
+                    next_to
+                ));
+            }
+        }
+
+        let traits = config
+            .traits
+            .into_iter()
+            .map(|(token_id, token_trait)| {
+                if !config.trait_multipliers.contains_key(&token_trait) {
+                    return Err(anyhow::anyhow!("No multiplier for trait {}", token_trait));
+                }
+                Ok((parse_token_id(&token_id)?, token_trait))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            enumeration: config.enumeration,
+            ranges,
+            traits,
+            trait_multipliers: config.trait_multipliers,
+            default_multiplier: config.default_multiplier,
+        })
+    }
+}
+
+/// How the token IDs each holder owns are enumerated.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TokenEnumeration {
+    /// Call ERC721Enumerable `tokenOfOwnerByIndex(owner, i)` for each token
+    /// the holder owns.
+    TokenOfOwnerByIndex,
+    /// Rebuild token ownership by replaying `Transfer` logs from `from_block`,
+    /// which should be at or before the contract deployment.
+    TransferLogs {
+        from_block: u64,
+        #[serde(default = "default_chunk_size")]
+        chunk_size: u64,
+    },
+}
+
+impl TokenMultipliers {
+    /// Get the multiplier for a token ID, in basis points.
+    pub fn get_multiplier(&self, token_id: U256) -> u64 {
+        if let Some(token_trait) = self.traits.get(&token_id) {
+            return self.trait_multipliers[token_trait];
+        }
+
+        let index = self.ranges.partition_point(|(from, _, _)| *from <= token_id);
+        match index.checked_sub(1).map(|index| self.ranges[index]) {
+            Some((_, to, multiplier)) if token_id <= to => multiplier,
+            _ => self.default_multiplier,
+        }
+    }
+
+    /// Get metadata about the multipliers.
+    pub fn get_metadata(&self) -> serde_json::Value {
+        let enumeration = match &self.enumeration {
+            TokenEnumeration::TokenOfOwnerByIndex => {
+                serde_json::json!({ "type": "token_of_owner_by_index" })
+            }
+            TokenEnumeration::TransferLogs { from_block, chunk_size } => serde_json::json!({
+                "type": "transfer_logs",
+                "from_block": from_block,
+                "chunk_size": chunk_size,
+            }),
+        };
+        serde_json::json!({
+            "enumeration": enumeration,
+            "ranges": self.ranges.iter().map(|(from, to, multiplier)| serde_json::json!({
+                "from": from.to_string(),
+                "to": to.to_string(),
+                "multiplier": multiplier,
+            })).collect::<Vec<_>>(),
+            "trait_count": self.traits.len(),
+            "trait_multipliers": self.trait_multipliers,
+            "default_multiplier": self.default_multiplier,
+        })
+    }
+}
+
+fn default_multiplier() -> u64 {
+    DEFAULT_WEIGHT_BPS
+}
+
+fn parse_token_id(value: &str) -> Result<U256> {
+    U256::from_str(value).map_err(|e| anyhow::anyhow!("Invalid token ID {}: {}", value, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(ranges: &str, traits: &str) -> Result<TokenMultipliers> {
+        let config = format!(
+            r#"{{
+                "enumeration": {{ "type": "token_of_owner_by_index" }},
+                "ranges": {},
+                "traits": {},
+                "trait_multipliers": {{ "legendary": 50000 }},
+                "default_multiplier": 5000
+            }}"#,
+            ranges, traits
+        );
+        Ok(serde_json::from_str(&config)?)
+    }
+
+    #[test]
+    fn ranges_are_parsed_and_sorted() {
+        let multipliers = parse(
+            r#"[
+                { "from": "0x65", "to": "200", "multiplier": 20000 },
+                { "from": "1", "to": "100", "multiplier": 30000 }
+            ]"#,
+            "{}",
+        )
+        .unwrap();
+        assert_eq!(
+            multipliers.ranges,
+            vec![
+                (U256::from(1), U256::from(100), 30000),
+                (U256::from(101), U256::from(200), 20000)
+            ]
+        );
+    }
+
+    #[test]
+    fn overlapping_ranges_are_rejected() {
+        let err = parse(
+            r#"[
+                { "from": "1", "to": "100", "multiplier": 30000 },
+                { "from": "100", "to": "200", "multiplier": 20000 }
+            ]"#,
+            "{}",
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("overlap"), "{}", err);
+    }
+
+    #[test]
+    fn invalid_ranges_are_rejected() {
+        assert!(parse(r#"[{ "from": "10", "to": "1", "multiplier": 1 }]"#, "{}").is_err());
+        assert!(parse(r#"[{ "from": "one", "to": "10", "multiplier": 1 }]"#, "{}").is_err());
+    }
+
+    #[test]
+    fn traits_without_a_multiplier_are_rejected() {
+        assert!(parse("[]", r#"{ "1": "common" }"#).is_err());
+    }
+
+    #[test]
+    fn trait_then_range_then_default() {
+        let multipliers = parse(
+            r#"[
+                { "from": "1", "to": "100", "multiplier": 30000 },
+                { "from": "201", "to": "300", "multiplier": 20000 }
+            ]"#,
+            r#"{ "50": "legendary" }"#,
+        )
+        .unwrap();
+        assert_eq!(multipliers.get_multiplier(U256::from(50)), 50000);
+        assert_eq!(multipliers.get_multiplier(U256::from(1)), 30000);
+        assert_eq!(multipliers.get_multiplier(U256::from(100)), 30000);
+        assert_eq!(multipliers.get_multiplier(U256::from(150)), 5000);
+        assert_eq!(multipliers.get_multiplier(U256::from(300)), 20000);
+        assert_eq!(multipliers.get_multiplier(U256::from(301)), 5000);
+        assert_eq!(multipliers.get_multiplier(U256::ZERO), 5000);
+    }
+
+    #[test]
+    fn metadata_counts_traits() {
+        let multipliers = parse("[]", r#"{ "1": "legendary", "2": "legendary" }"#).unwrap();
+        let metadata = multipliers.get_metadata();
+        assert_eq!(metadata["trait_count"], 2);
+        assert!(metadata.get("traits").is_none());
+    }
+}
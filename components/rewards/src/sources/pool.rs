@@ -0,0 +1,177 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use std::{collections::HashMap, str::FromStr};
+use wavs_wasi_utils::evm::alloy_primitives::{Address, U256, U512};
+
+use super::Source;
+
+/// Split a fixed pool of rewards per epoch among accounts, pro-rata to the
+/// rewards another source computes for them.
+///
+/// Each account gets `pool * rewards / total_rewards`, rounded down. The dust
+/// left over from rounding is paid out one unit at a time to the accounts with
+/// the largest remainders, ties going to the lowest address, so the whole pool
+/// is always distributed and the split is deterministic.
+pub struct PoolSource {
+    /// Source whose rewards are used as each account's share.
+    inner: Box<dyn Source>,
+    /// Rewards distributed per epoch.
+    pub pool: U256,
+}
+
+impl PoolSource {
+    pub fn new(inner: Box<dyn Source>, pool: U256) -> Self {
+        Self { inner, pool }
+    }
+}
+
+#[async_trait(?Send)]
+impl Source for PoolSource {
+    fn get_name(&self) -> &str {
+        self.inner.get_name()
+    }
+
+    async fn get_accounts(&self) -> Result<Vec<String>> {
+        self.inner.get_accounts().await
+    }
+
+    async fn get_rewards(&self, account: &str) -> Result<U256> {
+        let accounts = self.inner.get_accounts().await?;
+        let rewards = self.get_rewards_for_accounts(&accounts).await?;
+        Ok(rewards.get(account).copied().unwrap_or_default())
+    }
+
+    /// The pool is split among `accounts` only, so accounts left out by the
+    /// registry do not take a share.
+    async fn get_rewards_for_accounts(&self, accounts: &[String]) -> Result<HashMap<String, U256>> {
+        let shares = self.inner.get_rewards_for_accounts(accounts).await?;
+        split_pro_rata(self.pool, accounts, &shares)
+    }
+
+    async fn get_metadata(&self) -> Result<serde_json::Value> {
+        let mut metadata = self.inner.get_metadata().await?;
+        if let Some(metadata) = metadata.as_object_mut() {
+            metadata.insert("pool".to_string(), self.pool.to_string().into());
+        }
+        Ok(metadata)
+    }
+}
+
+/// Split `pool` among `accounts` pro-rata to `shares`, with exact integer math.
+pub(super) fn split_pro_rata(
+    pool: U256,
+    accounts: &[String],
+    shares: &HashMap<String, U256>,
+) -> Result<HashMap<String, U256>> {
+    let mut entries = Vec::with_capacity(accounts.len());
+    let mut total_shares = U512::ZERO;
+    for account in accounts {
+        let share = shares.get(account).copied().unwrap_or_default();
+        entries.push((Address::from_str(account)?, account, U512::from(share)));
+        total_shares += U512::from(share);
+    }
+
+    let mut rewards = HashMap::with_capacity(accounts.len());
+    if total_shares.is_zero() {
+        return Ok(rewards);
+    }
+
+    let pool = U512::from(pool);
+    let mut distributed = U512::ZERO;
+    let mut remainders = Vec::with_capacity(entries.len());
+    for (address, account, share) in entries {
+        let product = pool * share;
+        let amount = product / total_shares;
+        distributed += amount;
+        rewards.insert(account.clone(), U256::from(amount));
+        remainders.push((product % total_shares, address, account));
+    }
+
+    // The dust is less than the number of accounts with a remainder, so each
+    // gets at most one unit.
+    let dust: usize = (pool - distributed).to();
+    remainders.sort_by(|(a_rem, a_addr, _), (b_rem, b_addr, _)| {
+        b_rem.cmp(a_rem).then_with(|| a_addr.cmp(b_addr))
+    });
+    for (_, _, account) in remainders.into_iter().take(dust) {
+        if let Some(amount) = rewards.get_mut(account) {
+            *amount += U256::from(1);
+        }
+    }
+
+    Ok(rewards)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account(byte: u8) -> String {
+        Address::repeat_byte(byte).to_string()
+    }
+
+    fn split(pool: u64, shares: &[(u8, u64)]) -> HashMap<String, U256> {
+        let accounts = shares.iter().map(|(byte, _)| account(*byte)).collect::<Vec<_>>();
+        let shares = shares
+            .iter()
+            .map(|(byte, share)| (account(*byte), U256::from(*share)))
+            .collect::<HashMap<_, _>>();
+        split_pro_rata(U256::from(pool), &accounts, &shares).unwrap()
+    }
+
+    fn amount(rewards: &HashMap<String, U256>, byte: u8) -> U256 {
+        rewards.get(&account(byte)).copied().unwrap_or_default()
+    }
+
+    fn total(rewards: &HashMap<String, U256>) -> U256 {
+        rewards.values().copied().sum()
+    }
+
+    #[test]
+    fn shares_sum_exactly_to_pool() {
+        let rewards = split(100, &[(1, 1), (2, 3)]);
+        assert_eq!(amount(&rewards, 1), U256::from(25));
+        assert_eq!(amount(&rewards, 2), U256::from(75));
+        assert_eq!(total(&rewards), U256::from(100));
+    }
+
+    #[test]
+    fn dust_goes_to_largest_remainder() {
+        // 10 * 1/3 = 3.33 and 10 * 2/3 = 6.67, so the unit of dust goes to
+        // the second account.
+        let rewards = split(10, &[(1, 1), (2, 2)]);
+        assert_eq!(amount(&rewards, 1), U256::from(3));
+        assert_eq!(amount(&rewards, 2), U256::from(7));
+    }
+
+    #[test]
+    fn dust_ties_go_to_lowest_address_in_any_order() {
+        let forward = split(10, &[(1, 1), (2, 1), (3, 1)]);
+        let reversed = split(10, &[(3, 1), (2, 1), (1, 1)]);
+        assert_eq!(forward, reversed);
+        assert_eq!(amount(&forward, 1), U256::from(4));
+        assert_eq!(amount(&forward, 2), U256::from(3));
+        assert_eq!(amount(&forward, 3), U256::from(3));
+    }
+
+    #[test]
+    fn zero_total_shares_pays_nothing() {
+        let rewards = split(100, &[(1, 0), (2, 0)]);
+        assert_eq!(total(&rewards), U256::ZERO);
+    }
+
+    #[test]
+    fn single_account_gets_whole_pool() {
+        let rewards = split(1_000_000, &[(1, 7)]);
+        assert_eq!(amount(&rewards, 1), U256::from(1_000_000));
+    }
+
+    #[test]
+    fn pool_smaller_than_account_count() {
+        let rewards = split(2, &[(5, 1), (4, 1), (3, 1), (2, 1), (1, 1)]);
+        assert_eq!(amount(&rewards, 1), U256::from(1));
+        assert_eq!(amount(&rewards, 2), U256::from(1));
+        assert_eq!(amount(&rewards, 3), U256::ZERO);
+        assert_eq!(total(&rewards), U256::from(2));
+    }
+}
@@ -0,0 +1,473 @@
+use alloy_rpc_types::Filter;
+use alloy_sol_types::{sol, SolEvent, SolType};
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::{
+    cell::OnceCell,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    str::FromStr,
+};
+use wavs_wasi_utils::evm::alloy_primitives::{keccak256, Address, Bytes, U256};
+use wit_bindgen_rt::async_support::futures;
+
+use crate::provider::ChainProvider;
+
+use super::{
+    logs::{default_chunk_size, get_logs_chunked, topic_to_address, Transfer},
+    multicall::call,
+    multipliers::TokenMultipliers,
+    pool::split_pro_rata,
+    Source,
+};
+
+/// A staking contract that holds NFTs on behalf of their owners.
+#[derive(Clone, Debug, Deserialize)]
+pub struct StakingContract {
+    /// Staking contract address.
+    pub address: String,
+    /// View function that resolves who staked each NFT.
+    pub lookup: StakingLookup,
+    /// Block to start scanning deposits (`Transfer` logs into the staking
+    /// contract) from, at or before the staking contract deployment.
+    #[serde(default)]
+    pub from_block: u64,
+    #[serde(default = "default_chunk_size")]
+    pub chunk_size: u64,
+}
+
+/// How the depositor of a staked NFT is resolved.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StakingLookup {
+    /// `function(uint256 tokenId) returns (address)`, called for each token
+    /// ever deposited. Tokens that resolve to the zero address are unstaked.
+    StakedBy {
+        #[serde(default = "default_staked_by")]
+        function: String,
+    },
+    /// `function(address owner) returns (uint256[])`, called for each account
+    /// that ever deposited, returning the token IDs it has staked.
+    DepositsOf {
+        #[serde(default = "default_deposits_of")]
+        function: String,
+    },
+}
+
+impl StakingContract {
+    /// Get metadata about the staking contract.
+    pub fn get_metadata(&self) -> serde_json::Value {
+        let (lookup, function) = match &self.lookup {
+            StakingLookup::StakedBy { function } => ("staked_by", function),
+            StakingLookup::DepositsOf { function } => ("deposits_of", function),
+        };
+        serde_json::json!({
+            "address": self.address,
+            "lookup": lookup,
+            "function": function,
+            "from_block": self.from_block,
+        })
+    }
+}
+
+fn default_staked_by() -> String {
+    "stakedBy".to_string()
+}
+
+fn default_deposits_of() -> String {
+    "depositsOf".to_string()
+}
+
+/// Credit the rewards another source pays to staking contracts to the
+/// accounts that staked with them instead.
+///
+/// Each staking contract's rewards are split pro-rata to the number of NFTs
+/// each depositor has staked, or to the sum of their multipliers when the
+/// inner source weights tokens by multiplier.
+pub struct StakedSource {
+    /// Source whose rewards are attributed.
+    inner: Box<dyn Source>,
+    /// Provider for the source's chain.
+    provider: ChainProvider,
+    /// Snapshot block all queries are pinned to.
+    pub block: u64,
+    /// NFT contract address.
+    pub token: Address,
+    /// Staking contracts holding the NFTs.
+    pub staking_contracts: Vec<StakingContract>,
+    /// Multipliers the inner source weights each token by, if any.
+    pub multipliers: Option<TokenMultipliers>,
+    /// Token IDs staked per depositor, per staking contract, resolved once.
+    deposits: OnceCell<BTreeMap<Address, Deposits>>,
+}
+
+/// Token IDs each depositor has staked in a staking contract.
+type Deposits = HashMap<Address, Vec<U256>>;
+
+impl StakedSource {
+    pub fn new(
+        inner: Box<dyn Source>,
+        provider: ChainProvider,
+        block: u64,
+        token: &str,
+        staking_contracts: Vec<StakingContract>,
+    ) -> Result<Self> {
+        let token = Address::from_str(token)?;
+        Ok(Self {
+            inner,
+            provider,
+            block,
+            token,
+            staking_contracts,
+            multipliers: None,
+            deposits: OnceCell::new(),
+        })
+    }
+
+    /// Split staked rewards by the multipliers of the staked tokens, matching
+    /// an inner source that weights tokens by `multipliers`.
+    pub fn with_multipliers(mut self, multipliers: TokenMultipliers) -> Self {
+        self.multipliers = Some(multipliers);
+        self
+    }
+}
+
+#[async_trait(?Send)]
+impl Source for StakedSource {
+    fn get_name(&self) -> &str {
+        self.inner.get_name()
+    }
+
+    async fn get_accounts(&self) -> Result<Vec<String>> {
+        let deposits = self.query_deposits().await?;
+        let accounts = self.inner.get_accounts().await?;
+        staked_accounts(deposits, &accounts)
+    }
+
+    async fn get_rewards(&self, account: &str) -> Result<U256> {
+        let rewards = self.get_rewards_for_accounts(&[account.to_string()]).await?;
+        Ok(rewards.get(account).copied().unwrap_or_default())
+    }
+
+    async fn get_rewards_for_accounts(&self, accounts: &[String]) -> Result<HashMap<String, U256>> {
+        let deposits = self.query_deposits().await?;
+
+        // Staking contracts are queried too, since the registry may not have
+        // asked for them.
+        let mut queried = accounts.to_vec();
+        queried.extend(deposits.keys().map(|staking| staking.to_string()));
+        let inner_rewards = self.inner.get_rewards_for_accounts(&queried).await?;
+
+        attribute_rewards(deposits, self.multipliers.as_ref(), &inner_rewards, accounts)
+    }
+
+    async fn get_metadata(&self) -> Result<serde_json::Value> {
+        let mut metadata = self.inner.get_metadata().await?;
+        if let Some(metadata) = metadata.as_object_mut() {
+            metadata.insert(
+                "staking_contracts".to_string(),
+                self.staking_contracts.iter().map(|staking| staking.get_metadata()).collect(),
+            );
+        }
+        Ok(metadata)
+    }
+}
+
+impl StakedSource {
+    async fn query_deposits(&self) -> Result<&BTreeMap<Address, Deposits>> {
+        if let Some(deposits) = self.deposits.get() {
+            return Ok(deposits);
+        }
+
+        let mut deposits = BTreeMap::new();
+        for staking in &self.staking_contracts {
+            let address = Address::from_str(&staking.address)?;
+            deposits.insert(address, self.query_depositors(address, staking).await?);
+        }
+
+        Ok(self.deposits.get_or_init(|| deposits))
+    }
+
+    /// Get the token IDs each account has staked in a staking contract.
+    async fn query_depositors(
+        &self,
+        address: Address,
+        staking: &StakingContract,
+    ) -> Result<Deposits> {
+        let filter = Filter::new()
+            .address(self.token)
+            .event_signature(Transfer::SIGNATURE_HASH)
+            .topic2(address.into_word());
+        let logs = get_logs_chunked(
+            &self.provider,
+            filter,
+            staking.from_block,
+            self.block,
+            staking.chunk_size,
+        )
+        .await?;
+
+        let mut token_ids = BTreeSet::new();
+        let mut candidates = BTreeSet::new();
+        for log in logs {
+            let topics = log.topics();
+            if topics.len() != 4 {
+                return Err(anyhow::anyhow!("Staking is only supported for ERC721 tokens"));
+            }
+            candidates.insert(topic_to_address(&topics[1]));
+            token_ids.insert(U256::from_be_bytes(topics[3].0));
+        }
+
+        match &staking.lookup {
+            StakingLookup::StakedBy { function } => {
+                let selector = selector(&format!("{}(uint256)", function));
+                let results = futures::future::join_all(token_ids.iter().map(|token_id| {
+                    let input = [selector.as_slice(), &token_id.to_be_bytes::<32>()].concat();
+                    call(&self.provider, self.block, address, Bytes::from(input))
+                }))
+                .await;
+                let results = results.into_iter().collect::<Result<Vec<_>>>()?;
+                staked_by_depositors(token_ids.iter().copied().zip(results))
+            }
+            StakingLookup::DepositsOf { function } => {
+                let selector = selector(&format!("{}(address)", function));
+                let results = futures::future::join_all(candidates.iter().map(|candidate| {
+                    let input = [selector.as_slice(), candidate.into_word().as_slice()].concat();
+                    call(&self.provider, self.block, address, Bytes::from(input))
+                }))
+                .await;
+                let results = results.into_iter().collect::<Result<Vec<_>>>()?;
+                deposits_of_depositors(candidates.iter().copied().zip(results))
+            }
+        }
+    }
+}
+
+/// Get the token IDs each depositor has staked from `StakedBy` results, one
+/// ABI-encoded address per token ID. The zero address means unstaked.
+fn staked_by_depositors(results: impl IntoIterator<Item = (U256, Bytes)>) -> Result<Deposits> {
+    let mut depositors: Deposits = HashMap::new();
+    for (token_id, result) in results {
+        let depositor = <sol! { address }>::abi_decode(&result)?;
+        if !depositor.is_zero() {
+            depositors.entry(depositor).or_default().push(token_id);
+        }
+    }
+    Ok(depositors)
+}
+
+/// Get the token IDs each candidate has staked from `DepositsOf` results, each
+/// an ABI-encoded list of token IDs. Candidates with nothing staked are left
+/// out.
+fn deposits_of_depositors(results: impl IntoIterator<Item = (Address, Bytes)>) -> Result<Deposits> {
+    let mut depositors = HashMap::new();
+    for (candidate, result) in results {
+        let staked = <sol! { uint256[] }>::abi_decode(&result)?;
+        if !staked.is_empty() {
+            depositors.insert(candidate, staked);
+        }
+    }
+    Ok(depositors)
+}
+
+/// A depositor's share of its staking contract's rewards: the number of
+/// tokens it staked, or the sum of their multipliers.
+fn depositor_share(token_ids: &[U256], multipliers: Option<&TokenMultipliers>) -> U256 {
+    match multipliers {
+        Some(multipliers) => {
+            token_ids.iter().map(|token_id| U256::from(multipliers.get_multiplier(*token_id))).sum()
+        }
+        None => U256::from(token_ids.len()),
+    }
+}
+
+/// The inner source's accounts with staking contracts swapped for the
+/// accounts that staked with them.
+fn staked_accounts(
+    deposits: &BTreeMap<Address, Deposits>,
+    accounts: &[String],
+) -> Result<Vec<String>> {
+    let mut staked = BTreeSet::new();
+    for account in accounts {
+        let account = Address::from_str(account)?;
+        if !deposits.contains_key(&account) {
+            staked.insert(account);
+        }
+    }
+    for depositors in deposits.values() {
+        staked.extend(depositors.keys());
+    }
+    Ok(staked.into_iter().map(|account| account.to_string()).collect())
+}
+
+/// Rewards of `accounts`, given the inner source's rewards, with each staking
+/// contract's rewards split pro-rata to each depositor's share. Staking
+/// contracts themselves get nothing.
+fn attribute_rewards(
+    deposits: &BTreeMap<Address, Deposits>,
+    multipliers: Option<&TokenMultipliers>,
+    inner_rewards: &HashMap<String, U256>,
+    accounts: &[String],
+) -> Result<HashMap<String, U256>> {
+    let mut attributed: HashMap<Address, U256> = HashMap::new();
+    for (staking, depositors) in deposits {
+        let staked_rewards = inner_rewards.get(&staking.to_string()).copied().unwrap_or_default();
+        let depositor_accounts =
+            depositors.keys().map(|depositor| depositor.to_string()).collect::<Vec<_>>();
+        let shares = depositors
+            .iter()
+            .map(|(depositor, token_ids)| {
+                (depositor.to_string(), depositor_share(token_ids, multipliers))
+            })
+            .collect::<HashMap<_, _>>();
+        for (depositor, amount) in split_pro_rata(staked_rewards, &depositor_accounts, &shares)? {
+            let total = attributed.entry(Address::from_str(&depositor)?).or_default();
+            *total = total.checked_add(amount).ok_or(anyhow::anyhow!("Staked rewards overflow"))?;
+        }
+    }
+
+    let mut rewards = HashMap::with_capacity(accounts.len());
+    for account in accounts {
+        let address = Address::from_str(account)?;
+        let own = if deposits.contains_key(&address) {
+            U256::ZERO
+        } else {
+            inner_rewards.get(account).copied().unwrap_or_default()
+        };
+        let staked = attributed.get(&address).copied().unwrap_or_default();
+        let total = own.checked_add(staked).ok_or(anyhow::anyhow!("Staked rewards overflow"))?;
+        rewards.insert(account.clone(), total);
+    }
+    Ok(rewards)
+}
+
+/// The 4-byte selector of a function signature.
+fn selector(signature: &str) -> [u8; 4] {
+    keccak256(signature.as_bytes())[..4].try_into().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STAKING: Address = Address::repeat_byte(0x10);
+    const EMPTY_STAKING: Address = Address::repeat_byte(0x20);
+    const ALICE: Address = Address::repeat_byte(0x01);
+    const BOB: Address = Address::repeat_byte(0x02);
+    const CAROL: Address = Address::repeat_byte(0x03);
+
+    fn strings(accounts: &[Address]) -> Vec<String> {
+        accounts.iter().map(|account| account.to_string()).collect()
+    }
+
+    fn staked_by(depositor: Address) -> Bytes {
+        Bytes::from(<sol! { address }>::abi_encode(&depositor))
+    }
+
+    fn deposits_of(token_ids: &[u64]) -> Bytes {
+        let token_ids = token_ids.iter().map(|token_id| U256::from(*token_id)).collect::<Vec<_>>();
+        Bytes::from(<sol! { uint256[] }>::abi_encode(&token_ids))
+    }
+
+    fn token_ids(token_ids: &[u64]) -> Vec<U256> {
+        token_ids.iter().map(|token_id| U256::from(*token_id)).collect()
+    }
+
+    /// ALICE staked token 1 and BOB tokens 2 to 4 in STAKING; nobody resolved
+    /// as a depositor of EMPTY_STAKING.
+    fn deposits() -> BTreeMap<Address, Deposits> {
+        BTreeMap::from([
+            (STAKING, HashMap::from([(ALICE, token_ids(&[1])), (BOB, token_ids(&[2, 3, 4]))])),
+            (EMPTY_STAKING, HashMap::new()),
+        ])
+    }
+
+    fn attribute(
+        multipliers: Option<&TokenMultipliers>,
+        inner: &[(Address, u64)],
+        accounts: &[Address],
+    ) -> Vec<u64> {
+        let inner = inner
+            .iter()
+            .map(|(account, amount)| (account.to_string(), U256::from(*amount)))
+            .collect();
+        let rewards =
+            attribute_rewards(&deposits(), multipliers, &inner, &strings(accounts)).unwrap();
+        strings(accounts).iter().map(|account| rewards[account].to::<u64>()).collect()
+    }
+
+    fn rewards(inner: &[(Address, u64)], accounts: &[Address]) -> Vec<u64> {
+        attribute(None, inner, accounts)
+    }
+
+    #[test]
+    fn staking_rewards_go_to_depositors() {
+        let rewards = rewards(&[(STAKING, 400)], &[ALICE, BOB, STAKING]);
+        assert_eq!(rewards, vec![100, 300, 0]);
+    }
+
+    #[test]
+    fn depositors_keep_rewards_for_nfts_they_hold() {
+        let rewards = rewards(&[(STAKING, 400), (ALICE, 50), (CAROL, 20)], &[ALICE, BOB, CAROL]);
+        assert_eq!(rewards, vec![150, 300, 20]);
+    }
+
+    #[test]
+    fn staking_contract_without_depositors_pays_nobody() {
+        let rewards = rewards(&[(EMPTY_STAKING, 400), (ALICE, 50)], &[ALICE, EMPTY_STAKING]);
+        assert_eq!(rewards, vec![50, 0]);
+    }
+
+    #[test]
+    fn staking_rewards_are_split_by_multiplier() {
+        // Token 1 is worth 3x, so ALICE's one token matches BOB's three.
+        let multipliers: TokenMultipliers = serde_json::from_value(serde_json::json!({
+            "enumeration": { "type": "token_of_owner_by_index" },
+            "ranges": [{ "from": "1", "to": "1", "multiplier": 30000 }],
+        }))
+        .unwrap();
+        let rewards = attribute(Some(&multipliers), &[(STAKING, 600)], &[ALICE, BOB]);
+        assert_eq!(rewards, vec![300, 300]);
+    }
+
+    #[test]
+    fn accounts_swap_staking_contracts_for_depositors() {
+        let accounts = staked_accounts(&deposits(), &strings(&[STAKING, EMPTY_STAKING, CAROL]));
+        assert_eq!(accounts.unwrap(), strings(&[ALICE, BOB, CAROL]));
+    }
+
+    #[test]
+    fn staked_by_collects_tokens_per_depositor() {
+        let depositors = staked_by_depositors(token_ids(&[1, 2, 3, 4]).into_iter().zip([
+            staked_by(ALICE),
+            staked_by(BOB),
+            staked_by(Address::ZERO),
+            staked_by(ALICE),
+        ]))
+        .unwrap();
+        assert_eq!(
+            depositors,
+            HashMap::from([(ALICE, token_ids(&[1, 4])), (BOB, token_ids(&[2]))])
+        );
+    }
+
+    #[test]
+    fn deposits_of_collects_staked_token_ids() {
+        let depositors = deposits_of_depositors([
+            (ALICE, deposits_of(&[7, 9])),
+            (BOB, deposits_of(&[])),
+            (CAROL, deposits_of(&[3])),
+        ])
+        .unwrap();
+        assert_eq!(
+            depositors,
+            HashMap::from([(ALICE, token_ids(&[7, 9])), (CAROL, token_ids(&[3]))])
+        );
+    }
+
+    #[test]
+    fn malformed_lookup_results_are_errors() {
+        assert!(deposits_of_depositors([(ALICE, Bytes::from_static(&[1, 2, 3]))]).is_err());
+        assert!(staked_by_depositors([(U256::from(1), Bytes::new())]).is_err());
+    }
+}
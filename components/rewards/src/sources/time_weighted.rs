@@ -0,0 +1,311 @@
+use alloy_rpc_types::Filter;
+use alloy_sol_types::SolEvent;
+use anyhow::Result;
+use async_trait::async_trait;
+use std::{cell::OnceCell, collections::HashMap, str::FromStr};
+use wavs_wasi_utils::evm::alloy_primitives::{Address, U256};
+
+use crate::provider::ChainProvider;
+
+use super::{
+    logs::{get_logs_chunked, topic_to_address, Transfer},
+    Source,
+};
+
+/// Compute rewards from how long an ERC20 or ERC721 token is held within an
+/// epoch, i.e. balance × blocks held between `start_block` and `end_block`.
+///
+/// Balances are rebuilt by replaying `Transfer` logs from `from_block`, which
+/// should be at or before the contract deployment.
+pub struct TimeWeightedSource {
+    /// Chain the contract is deployed on.
+    pub chain_name: String,
+    /// Provider for the chain.
+    provider: ChainProvider,
+    /// Contract address.
+    pub address: Address,
+    /// Rewards per whole token held for one block.
+    pub rewards_per_token_block: U256,
+    /// Token decimals (0 for ERC721).
+    pub decimals: u8,
+    /// Block to start replaying `Transfer` logs from.
+    pub from_block: u64,
+    /// First block of the epoch.
+    pub start_block: u64,
+    /// Last block of the epoch.
+    pub end_block: u64,
+    /// Number of blocks requested per `eth_getLogs` call.
+    pub chunk_size: u64,
+    /// Balance × blocks held per account, computed once from the logs.
+    holdings: OnceCell<HashMap<Address, U256>>,
+}
+
+impl TimeWeightedSource {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        chain_name: &str,
+        provider: ChainProvider,
+        address: &str,
+        rewards_per_token_block: U256,
+        decimals: u8,
+        from_block: u64,
+        start_block: u64,
+        end_block: u64,
+        chunk_size: u64,
+    ) -> Result<Self> {
+        let contract = Address::from_str(address)?;
+        Ok(Self {
+            chain_name: chain_name.to_string(),
+            provider,
+            address: contract,
+            rewards_per_token_block,
+            decimals,
+            from_block,
+            start_block,
+            end_block,
+            chunk_size,
+            holdings: OnceCell::new(),
+        })
+    }
+}
+
+#[async_trait(?Send)]
+impl Source for TimeWeightedSource {
+    fn get_name(&self) -> &str {
+        "time-weighted holdings"
+    }
+
+    async fn get_accounts(&self) -> Result<Vec<String>> {
+        let holdings = self.query_holdings().await?;
+        Ok(holdings
+            .iter()
+            .filter(|(_, held)| !held.is_zero())
+            .map(|(account, _)| account.to_string())
+            .collect())
+    }
+
+    async fn get_rewards(&self, account: &str) -> Result<U256> {
+        let address = Address::from_str(account)?;
+        let held = self.query_holdings().await?.get(&address).copied().unwrap_or_default();
+        self.rewards_for_holding(held)
+    }
+
+    async fn get_rewards_for_accounts(&self, accounts: &[String]) -> Result<HashMap<String, U256>> {
+        let holdings = self.query_holdings().await?;
+        let mut rewards = HashMap::with_capacity(accounts.len());
+        for account in accounts {
+            let held = holdings.get(&Address::from_str(account)?).copied().unwrap_or_default();
+            rewards.insert(account.clone(), self.rewards_for_holding(held)?);
+        }
+        Ok(rewards)
+    }
+
+    async fn get_metadata(&self) -> Result<serde_json::Value> {
+        Ok(serde_json::json!({
+            "chain": self.chain_name,
+            "address": self.address.to_string(),
+            "rewards_per_token_block": self.rewards_per_token_block.to_string(),
+            "decimals": self.decimals,
+            "from_block": self.from_block,
+            "start_block": self.start_block,
+            "end_block": self.end_block,
+        }))
+    }
+}
+
+impl TimeWeightedSource {
+    /// Rewards for a balance × blocks amount, at `rewards_per_token_block`.
+    fn rewards_for_holding(&self, held: U256) -> Result<U256> {
+        let unit = U256::from(10).pow(U256::from(self.decimals));
+        let rewards = held
+            .checked_mul(self.rewards_per_token_block)
+            .ok_or(anyhow::anyhow!("Time-weighted rewards overflow"))?
+            / unit;
+        Ok(rewards)
+    }
+
+    async fn query_holdings(&self) -> Result<&HashMap<Address, U256>> {
+        if let Some(holdings) = self.holdings.get() {
+            return Ok(holdings);
+        }
+
+        if self.start_block > self.end_block {
+            return Err(anyhow::anyhow!(
+                "Epoch start block {} is after end block {}",
+                self.start_block,
+                self.end_block
+            ));
+        }
+
+        let filter = Filter::new().address(self.address).event_signature(Transfer::SIGNATURE_HASH);
+        let logs = get_logs_chunked(
+            &self.provider,
+            filter,
+            self.from_block,
+            self.end_block,
+            self.chunk_size,
+        )
+        .await?;
+
+        let mut tracker = HoldingTracker::new(self.start_block, self.end_block);
+        for log in logs {
+            let block =
+                log.block_number.ok_or(anyhow::anyhow!("Transfer log is missing block number"))?;
+            let topics = log.topics();
+            let value = match topics.len() {
+                // ERC721: one token per transfer.
+                4 => U256::from(1),
+                // ERC20: value in the log data.
+                3 => U256::from_be_slice(&log.data().data),
+                _ => continue,
+            };
+
+            tracker.transfer(
+                topic_to_address(&topics[1]),
+                topic_to_address(&topics[2]),
+                value,
+                block,
+            )?;
+        }
+
+        let holdings = tracker.finish()?;
+        Ok(self.holdings.get_or_init(|| holdings))
+    }
+}
+
+/// Tracks running balances and accumulates balance × blocks held within an
+/// epoch as transfers are replayed in order.
+struct HoldingTracker {
+    start_block: u64,
+    end_block: u64,
+    /// Current balance and the block it was last accrued up to.
+    balances: HashMap<Address, (U256, u64)>,
+    held: HashMap<Address, U256>,
+}
+
+impl HoldingTracker {
+    fn new(start_block: u64, end_block: u64) -> Self {
+        Self { start_block, end_block, balances: HashMap::new(), held: HashMap::new() }
+    }
+
+    /// Apply a transfer made at `block`. The new balances count from `block`
+    /// onward.
+    fn transfer(&mut self, from: Address, to: Address, value: U256, block: u64) -> Result<()> {
+        let block = block.clamp(self.start_block, self.end_block);
+        if from != Address::ZERO {
+            self.accrue(from, block)?;
+            let (balance, _) = self.balances.entry(from).or_default();
+            *balance = balance.saturating_sub(value);
+        }
+        if to != Address::ZERO {
+            self.accrue(to, block)?;
+            let (balance, _) = self.balances.entry(to).or_default();
+            *balance = balance.saturating_add(value);
+        }
+        Ok(())
+    }
+
+    /// Accrue an account's current balance for the blocks since it was last
+    /// accrued, up to `block`.
+    fn accrue(&mut self, account: Address, block: u64) -> Result<()> {
+        let (balance, last_block) = self.balances.entry(account).or_insert((U256::ZERO, 0));
+        let since = (*last_block).max(self.start_block);
+        if block > since && !balance.is_zero() {
+            let amount = balance
+                .checked_mul(U256::from(block - since))
+                .ok_or(anyhow::anyhow!("Time-weighted balance overflow"))?;
+            let held = self.held.entry(account).or_default();
+            *held = held.saturating_add(amount);
+        }
+        *last_block = block;
+        Ok(())
+    }
+
+    /// Accrue every account up to the end of the epoch.
+    fn finish(mut self) -> Result<HashMap<Address, U256>> {
+        let accounts = self.balances.keys().copied().collect::<Vec<_>>();
+        for account in accounts {
+            self.accrue(account, self.end_block)?;
+        }
+        Ok(self.held)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: Address = Address::repeat_byte(0xaa);
+    const B: Address = Address::repeat_byte(0xbb);
+    const C: Address = Address::repeat_byte(0xcc);
+
+    fn held(holdings: &HashMap<Address, U256>, account: Address) -> U256 {
+        holdings.get(&account).copied().unwrap_or_default()
+    }
+
+    #[test]
+    fn mint_transfer_and_burn_within_epoch() {
+        let mut tracker = HoldingTracker::new(100, 200);
+        tracker.transfer(Address::ZERO, A, U256::from(10), 110).unwrap();
+        tracker.transfer(A, B, U256::from(4), 150).unwrap();
+        tracker.transfer(B, Address::ZERO, U256::from(4), 180).unwrap();
+        let holdings = tracker.finish().unwrap();
+
+        // 10 × 40 blocks, then 6 × 50 blocks.
+        assert_eq!(held(&holdings, A), U256::from(700));
+        // 4 × 30 blocks until the burn.
+        assert_eq!(held(&holdings, B), U256::from(120));
+        assert!(!holdings.contains_key(&Address::ZERO));
+    }
+
+    #[test]
+    fn holdings_before_epoch_count_from_start_block() {
+        let mut tracker = HoldingTracker::new(100, 200);
+        tracker.transfer(Address::ZERO, A, U256::from(5), 10).unwrap();
+        tracker.transfer(A, B, U256::from(2), 60).unwrap();
+        tracker.transfer(A, C, U256::from(3), 150).unwrap();
+        let holdings = tracker.finish().unwrap();
+
+        assert_eq!(held(&holdings, A), U256::from(150));
+        assert_eq!(held(&holdings, B), U256::from(200));
+        assert_eq!(held(&holdings, C), U256::from(150));
+    }
+
+    #[test]
+    fn transfers_after_epoch_are_ignored() {
+        let mut tracker = HoldingTracker::new(100, 200);
+        tracker.transfer(Address::ZERO, A, U256::from(1), 100).unwrap();
+        tracker.transfer(A, B, U256::from(1), 250).unwrap();
+        let holdings = tracker.finish().unwrap();
+
+        assert_eq!(held(&holdings, A), U256::from(100));
+        assert_eq!(held(&holdings, B), U256::ZERO);
+    }
+
+    #[test]
+    fn balance_minted_before_replay_saturates_at_zero() {
+        // Logs replayed from after the mint: A's balance is unknown, so its
+        // transfer out cannot underflow.
+        let mut tracker = HoldingTracker::new(100, 200);
+        tracker.transfer(A, B, U256::from(3), 120).unwrap();
+        let holdings = tracker.finish().unwrap();
+
+        assert_eq!(held(&holdings, A), U256::ZERO);
+        assert_eq!(held(&holdings, B), U256::from(240));
+    }
+
+    #[test]
+    fn several_transfers_in_one_block() {
+        let mut tracker = HoldingTracker::new(100, 200);
+        tracker.transfer(Address::ZERO, A, U256::from(10), 100).unwrap();
+        tracker.transfer(A, B, U256::from(5), 150).unwrap();
+        tracker.transfer(A, B, U256::from(5), 150).unwrap();
+        tracker.transfer(B, C, U256::from(10), 150).unwrap();
+        tracker.transfer(Address::ZERO, C, U256::from(1), 150).unwrap();
+        let holdings = tracker.finish().unwrap();
+
+        assert_eq!(held(&holdings, A), U256::from(500));
+        assert_eq!(held(&holdings, B), U256::ZERO);
+        assert_eq!(held(&holdings, C), U256::from(550));
+    }
+}
@@ -0,0 +1,366 @@
+use alloy_rpc_types::Filter;
+use alloy_sol_types::{sol, SolCall, SolEvent};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::{
+    cell::OnceCell,
+    collections::{BTreeSet, HashMap, HashSet},
+    str::FromStr,
+};
+use wavs_wasi_utils::evm::alloy_primitives::{
+    aliases::{I24, U24},
+    Address, Bytes, U256,
+};
+
+use crate::provider::ChainProvider;
+
+use super::{
+    logs::{get_logs_chunked, query_owners_of_tokens, DEFAULT_LOGS_CHUNK_SIZE},
+    multicall::{batch_call, call, DEFAULT_MULTICALL_CHUNK_SIZE},
+    Source,
+};
+
+/// Compute rewards for liquidity providers of a Uniswap V3 pool, proportional
+/// to the liquidity of their positions that is in range at the snapshot.
+///
+/// Positions are discovered from the pool's `Mint` logs from `from_block`,
+/// which should be at or before the pool deployment: the position manager's
+/// `IncreaseLiquidity` logs in the same transactions give the token IDs, and
+/// their `Transfer` logs give the owners. A position is in range when
+/// `tickLower <= tick < tickUpper` for the pool's tick at the snapshot block.
+/// Pair with a fixed `pool` in the source spec to split a set amount by
+/// liquidity.
+pub struct UniswapV3Source {
+    /// Chain the pool is deployed on.
+    pub chain_name: String,
+    /// Provider for the chain.
+    provider: ChainProvider,
+    /// Snapshot block all queries are pinned to.
+    pub block: u64,
+    /// NonfungiblePositionManager address.
+    pub position_manager: Address,
+    /// Pool address.
+    pub pool: Address,
+    /// Rewards per unit of in-range liquidity.
+    pub rewards_per_liquidity: U256,
+    /// Block to start scanning the pool's `Mint` logs from.
+    pub from_block: u64,
+    /// Number of blocks requested per `eth_getLogs` call.
+    pub chunk_size: u64,
+    /// Number of position queries batched per Multicall3 call (0 disables batching).
+    pub batch_size: usize,
+    /// In-range liquidity per position owner, computed once.
+    liquidity: OnceCell<HashMap<Address, U256>>,
+}
+
+impl UniswapV3Source {
+    pub fn new(
+        chain_name: &str,
+        provider: ChainProvider,
+        block: u64,
+        position_manager: &str,
+        pool: &str,
+        rewards_per_liquidity: U256,
+        from_block: u64,
+    ) -> Result<Self> {
+        let position_manager = Address::from_str(position_manager)?;
+        let pool = Address::from_str(pool)?;
+        Ok(Self {
+            chain_name: chain_name.to_string(),
+            provider,
+            block,
+            position_manager,
+            pool,
+            rewards_per_liquidity,
+            from_block,
+            chunk_size: DEFAULT_LOGS_CHUNK_SIZE,
+            batch_size: DEFAULT_MULTICALL_CHUNK_SIZE,
+            liquidity: OnceCell::new(),
+        })
+    }
+
+    /// Use a different number of blocks per `eth_getLogs` call.
+    pub fn with_chunk_size(mut self, chunk_size: u64) -> Self {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// Use a different Multicall3 batch size for position queries.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+}
+
+#[async_trait(?Send)]
+impl Source for UniswapV3Source {
+    fn get_name(&self) -> &str {
+        "Uniswap V3 liquidity"
+    }
+
+    async fn get_accounts(&self) -> Result<Vec<String>> {
+        let liquidity = self.query_liquidity().await?;
+        Ok(liquidity
+            .iter()
+            .filter(|(_, liquidity)| !liquidity.is_zero())
+            .map(|(account, _)| account.to_string())
+            .collect())
+    }
+
+    async fn get_rewards(&self, account: &str) -> Result<U256> {
+        let address = Address::from_str(account)?;
+        let liquidity = self.query_liquidity().await?.get(&address).copied().unwrap_or_default();
+        self.rewards_for_liquidity(liquidity)
+    }
+
+    async fn get_rewards_for_accounts(&self, accounts: &[String]) -> Result<HashMap<String, U256>> {
+        let liquidity = self.query_liquidity().await?;
+        let mut rewards = HashMap::with_capacity(accounts.len());
+        for account in accounts {
+            let owned = liquidity.get(&Address::from_str(account)?).copied().unwrap_or_default();
+            rewards.insert(account.clone(), self.rewards_for_liquidity(owned)?);
+        }
+        Ok(rewards)
+    }
+
+    async fn get_metadata(&self) -> Result<serde_json::Value> {
+        Ok(serde_json::json!({
+            "chain": self.chain_name,
+            "block": self.block,
+            "position_manager": self.position_manager.to_string(),
+            "pool_address": self.pool.to_string(),
+            "rewards_per_liquidity": self.rewards_per_liquidity.to_string(),
+            "from_block": self.from_block,
+        }))
+    }
+}
+
+impl UniswapV3Source {
+    fn rewards_for_liquidity(&self, liquidity: U256) -> Result<U256> {
+        liquidity
+            .checked_mul(self.rewards_per_liquidity)
+            .ok_or(anyhow::anyhow!("Uniswap V3 rewards overflow"))
+    }
+
+    async fn call_pool(&self, input: Bytes) -> Result<Bytes> {
+        call(&self.provider, self.block, self.pool, input).await
+    }
+
+    /// Token IDs of the position manager's positions that added liquidity to
+    /// the pool, from the `IncreaseLiquidity` logs of the transactions that
+    /// minted into it. Logs are only requested for blocks with a pool mint.
+    async fn query_position_ids(&self) -> Result<Vec<U256>> {
+        let mint_filter = Filter::new()
+            .address(self.pool)
+            .event_signature(IUniswapV3Pool::Mint::SIGNATURE_HASH)
+            .topic1(self.position_manager.into_word());
+        let mints = get_logs_chunked(
+            &self.provider,
+            mint_filter,
+            self.from_block,
+            self.block,
+            self.chunk_size,
+        )
+        .await?;
+        let transactions =
+            mints.iter().filter_map(|log| log.transaction_hash).collect::<HashSet<_>>();
+        let blocks = mints.iter().filter_map(|log| log.block_number).collect::<BTreeSet<_>>();
+
+        let increase_filter = Filter::new()
+            .address(self.position_manager)
+            .event_signature(INonfungiblePositionManager::IncreaseLiquidity::SIGNATURE_HASH);
+        let mut token_ids = BTreeSet::new();
+        let mut blocks = blocks.into_iter().peekable();
+        while let Some(first) = blocks.next() {
+            let mut last = first;
+            while let Some(block) = blocks.next_if(|block| block - first < self.chunk_size) {
+                last = block;
+            }
+
+            let logs = get_logs_chunked(
+                &self.provider,
+                increase_filter.clone(),
+                first,
+                last,
+                self.chunk_size,
+            )
+            .await?;
+            // A transaction can mint into several pools; positions in other
+            // pools are dropped once their tokens and fee are read.
+            for log in logs {
+                if !log.transaction_hash.is_some_and(|hash| transactions.contains(&hash)) {
+                    continue;
+                }
+                if let Some(topic) = log.topics().get(1) {
+                    token_ids.insert(U256::from_be_bytes(topic.0));
+                }
+            }
+        }
+
+        Ok(token_ids.into_iter().collect())
+    }
+
+    async fn query_liquidity(&self) -> Result<&HashMap<Address, U256>> {
+        if let Some(liquidity) = self.liquidity.get() {
+            return Ok(liquidity);
+        }
+
+        let pool = PoolState {
+            token0: IUniswapV3Pool::token0Call::abi_decode_returns(
+                &self.call_pool(IUniswapV3Pool::token0Call {}.abi_encode().into()).await?,
+            )?,
+            token1: IUniswapV3Pool::token1Call::abi_decode_returns(
+                &self.call_pool(IUniswapV3Pool::token1Call {}.abi_encode().into()).await?,
+            )?,
+            fee: IUniswapV3Pool::feeCall::abi_decode_returns(
+                &self.call_pool(IUniswapV3Pool::feeCall {}.abi_encode().into()).await?,
+            )?,
+            tick: IUniswapV3Pool::slot0Call::abi_decode_returns(
+                &self.call_pool(IUniswapV3Pool::slot0Call {}.abi_encode().into()).await?,
+            )?
+            .tick,
+        };
+
+        let token_ids = self.query_position_ids().await?;
+        let owners = query_owners_of_tokens(
+            &self.provider,
+            self.position_manager,
+            &token_ids,
+            self.from_block,
+            self.block,
+            self.chunk_size,
+        )
+        .await?
+        .into_iter()
+        .collect::<Vec<_>>();
+
+        let calls = owners
+            .iter()
+            .map(|(token_id, _)| {
+                let positions_call =
+                    INonfungiblePositionManager::positionsCall { tokenId: *token_id };
+                (self.position_manager, positions_call.abi_encode().into())
+            })
+            .collect::<Vec<_>>();
+        let results = batch_call(&self.provider, self.block, &calls, self.batch_size).await?;
+
+        let mut liquidity: HashMap<Address, U256> = HashMap::new();
+        for ((_, owner), result) in owners.into_iter().zip(results) {
+            let position = INonfungiblePositionManager::positionsCall::abi_decode_returns(&result)?;
+            let active = pool.active_liquidity(&position);
+            if active == 0 {
+                continue;
+            }
+
+            let total = liquidity.entry(owner).or_default();
+            *total = total
+                .checked_add(U256::from(active))
+                .ok_or(anyhow::anyhow!("Uniswap V3 liquidity overflow"))?;
+        }
+
+        Ok(self.liquidity.get_or_init(|| liquidity))
+    }
+}
+
+/// The pool's identity and current tick at the snapshot block.
+struct PoolState {
+    token0: Address,
+    token1: Address,
+    fee: U24,
+    tick: I24,
+}
+
+impl PoolState {
+    /// The liquidity a position provides to this pool at the current tick:
+    /// zero unless the position has the pool's tokens and fee and
+    /// `tickLower <= tick < tickUpper`.
+    fn active_liquidity(&self, position: &INonfungiblePositionManager::positionsReturn) -> u128 {
+        let in_pool = position.token0 == self.token0
+            && position.token1 == self.token1
+            && position.fee == self.fee;
+        let in_range = position.tickLower <= self.tick && self.tick < position.tickUpper;
+        if in_pool && in_range {
+            position.liquidity
+        } else {
+            0
+        }
+    }
+}
+
+sol! {
+    interface IUniswapV3Pool {
+        function token0() external view returns (address);
+        function token1() external view returns (address);
+        function fee() external view returns (uint24);
+        function slot0() external view returns (uint160 sqrtPriceX96, int24 tick, uint16 observationIndex, uint16 observationCardinality, uint16 observationCardinalityNext, uint8 feeProtocol, bool unlocked);
+        event Mint(address sender, address indexed owner, int24 indexed tickLower, int24 indexed tickUpper, uint128 amount, uint256 amount0, uint256 amount1);
+    }
+    interface INonfungiblePositionManager {
+        event IncreaseLiquidity(uint256 indexed tokenId, uint128 liquidity, uint256 amount0, uint256 amount1);
+        function positions(uint256 tokenId) external view returns (uint96 nonce, address operator, address token0, address token1, uint24 fee, int24 tickLower, int24 tickUpper, uint128 liquidity, uint256 feeGrowthInside0LastX128, uint256 feeGrowthInside1LastX128, uint128 tokensOwed0, uint128 tokensOwed1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wavs_wasi_utils::evm::alloy_primitives::aliases::U96;
+
+    const WETH: Address = Address::repeat_byte(0x01);
+    const USDC: Address = Address::repeat_byte(0x02);
+
+    fn pool(tick: i32) -> PoolState {
+        PoolState {
+            token0: WETH,
+            token1: USDC,
+            fee: U24::from(3000),
+            tick: I24::try_from(tick).unwrap(),
+        }
+    }
+
+    fn position(
+        fee: u32,
+        tick_lower: i32,
+        tick_upper: i32,
+        liquidity: u128,
+    ) -> INonfungiblePositionManager::positionsReturn {
+        INonfungiblePositionManager::positionsReturn {
+            nonce: U96::ZERO,
+            operator: Address::ZERO,
+            token0: WETH,
+            token1: USDC,
+            fee: U24::from(fee),
+            tickLower: I24::try_from(tick_lower).unwrap(),
+            tickUpper: I24::try_from(tick_upper).unwrap(),
+            liquidity,
+            feeGrowthInside0LastX128: U256::ZERO,
+            feeGrowthInside1LastX128: U256::ZERO,
+            tokensOwed0: 0,
+            tokensOwed1: 0,
+        }
+    }
+
+    #[test]
+    fn tick_lower_is_inclusive_and_tick_upper_exclusive() {
+        let position = position(3000, -60, 60, 500);
+        assert_eq!(pool(-61).active_liquidity(&position), 0);
+        assert_eq!(pool(-60).active_liquidity(&position), 500);
+        assert_eq!(pool(59).active_liquidity(&position), 500);
+        assert_eq!(pool(60).active_liquidity(&position), 0);
+    }
+
+    #[test]
+    fn positions_in_other_pools_are_ignored() {
+        // Same tokens, different fee tier.
+        assert_eq!(pool(0).active_liquidity(&position(500, -60, 60, 500)), 0);
+
+        let mut other_tokens = position(3000, -60, 60, 500);
+        other_tokens.token1 = Address::repeat_byte(0x03);
+        assert_eq!(pool(0).active_liquidity(&other_tokens), 0);
+    }
+
+    #[test]
+    fn closed_positions_have_no_liquidity() {
+        assert_eq!(pool(0).active_liquidity(&position(3000, -60, 60, 0)), 0);
+    }
+}
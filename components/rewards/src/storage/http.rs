@@ -0,0 +1,50 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use cid::Cid;
+use wstd::http::{IntoBody, Request};
+
+use super::{send, StorageBackend};
+use crate::ipfs::decode_ipfs_cid;
+
+/// Upload files with a plain HTTP PUT to `{url}/{name}`.
+///
+/// The CID is read from the `Ipfs-Hash` response header if present, otherwise
+/// from the response body.
+pub struct HttpPutStorage {
+    /// Base URL files are uploaded under.
+    pub url: String,
+    api_key: Option<String>,
+}
+
+impl HttpPutStorage {
+    pub fn new(url: &str, api_key: Option<String>) -> Self {
+        Self { url: url.trim_end_matches('/').to_string(), api_key }
+    }
+}
+
+#[async_trait(?Send)]
+impl StorageBackend for HttpPutStorage {
+    fn get_name(&self) -> &str {
+        "http"
+    }
+
+    async fn upload(&self, data: &[u8], name: &str) -> Result<Cid> {
+        let url = format!("{}/{}", self.url, name);
+        eprintln!("Uploading file with HTTP PUT: {}", url);
+
+        let mut request = Request::put(&url).header("Content-Type", "application/json");
+        if let Some(api_key) = &self.api_key {
+            request = request.header("Authorization", &format!("Bearer {}", api_key));
+        }
+        let request = request.body(data.to_vec().into_body())?;
+
+        let (headers, body_buf) = send(request).await?;
+
+        let hash = match headers.get("Ipfs-Hash") {
+            Some(value) => value.to_str()?.to_string(),
+            None => String::from_utf8(body_buf)?.trim().to_string(),
+        };
+
+        decode_ipfs_cid(&hash).map_err(|e| anyhow::anyhow!("Failed to decode IPFS CID: {}", e))
+    }
+}
@@ -0,0 +1,63 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use cid::Cid;
+use serde::Deserialize;
+use wstd::http::{IntoBody, Request};
+
+use super::{multipart_body, send, StorageBackend};
+use crate::ipfs::decode_ipfs_cid;
+
+/// Upload files to an IPFS Kubo node through its `/api/v0/add` RPC.
+pub struct KuboStorage {
+    /// Base URL of the Kubo RPC API, e.g. `http://127.0.0.1:5001`.
+    pub api_url: String,
+}
+
+impl KuboStorage {
+    pub fn new(api_url: &str) -> Self {
+        Self { api_url: api_url.trim_end_matches('/').to_string() }
+    }
+}
+
+#[async_trait(?Send)]
+impl StorageBackend for KuboStorage {
+    fn get_name(&self) -> &str {
+        "kubo"
+    }
+
+    async fn upload(&self, data: &[u8], name: &str) -> Result<Cid> {
+        eprintln!("Uploading file to Kubo: {}", name);
+
+        let boundary = "----RustBoundary";
+        let request_body = multipart_body(boundary, name, data, &[]);
+
+        // Raw leaves, the default chunker and CIDv1 match the CID computed
+        // locally for the file.
+        let url = format!(
+            "{}/api/v0/add?cid-version=1&raw-leaves=true&chunker=size-262144&pin=true",
+            self.api_url
+        );
+        let request = Request::post(&url)
+            .header("Content-Type", &format!("multipart/form-data; boundary={}", boundary))
+            .body(request_body.into_body())?;
+
+        let (_, body_buf) = send(request).await?;
+
+        #[derive(Debug, Deserialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct KuboAddResponse {
+            hash: String,
+        }
+
+        let hash = serde_json::from_slice::<KuboAddResponse>(&body_buf)
+            .map_err(|_| {
+                anyhow::anyhow!(
+                    "Could not extract hash from response: {}",
+                    String::from_utf8_lossy(&body_buf)
+                )
+            })?
+            .hash;
+
+        decode_ipfs_cid(&hash).map_err(|e| anyhow::anyhow!("Failed to decode IPFS CID: {}", e))
+    }
+}
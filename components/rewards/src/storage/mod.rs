@@ -0,0 +1,73 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use cid::Cid;
+use wstd::http::{Body, Client, Request};
+use wstd::io::AsyncRead;
+
+pub mod http;
+pub mod kubo;
+pub mod pinata;
+
+/// A backend that stores the tree data and returns its IPFS CID.
+#[async_trait(?Send)]
+pub trait StorageBackend {
+    /// Get the name of the backend.
+    fn get_name(&self) -> &str;
+
+    /// Upload a file and return its CID.
+    async fn upload(&self, data: &[u8], name: &str) -> Result<Cid>;
+}
+
+/// Build a multipart/form-data body containing the file, followed by any
+/// extra text fields.
+fn multipart_body(boundary: &str, name: &str, data: &[u8], fields: &[(&str, &str)]) -> Vec<u8> {
+    let mut body = format!(
+        "--{}\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\n\
+        Content-Type: application/octet-stream\r\n\r\n",
+        boundary, name
+    )
+    .into_bytes();
+    body.extend_from_slice(data);
+    body.extend_from_slice(b"\r\n");
+
+    for (field, value) in fields {
+        body.extend_from_slice(
+            format!(
+                "--{}\r\n\
+                Content-Disposition: form-data; name=\"{}\"\r\n\r\n\
+                {}\r\n",
+                boundary, field, value
+            )
+            .as_bytes(),
+        );
+    }
+
+    body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+    body
+}
+
+/// Send a request and return the response headers and body, failing on a
+/// non-success status.
+async fn send<B: Body>(request: Request<B>) -> Result<(wstd::http::HeaderMap, Vec<u8>)> {
+    let mut response = Client::new().send(request).await?;
+
+    let mut body_buf = Vec::new();
+    response.body_mut().read_to_end(&mut body_buf).await?;
+
+    if response.status().is_success() {
+        // Log the raw response for debugging
+        let response_str = std::str::from_utf8(&body_buf)
+            .map_err(|e| anyhow::anyhow!("Failed to convert response to string: {}", e))?;
+        eprintln!("Storage API Response: {}", response_str);
+
+        Ok((response.headers().clone(), body_buf))
+    } else {
+        let error_body = std::str::from_utf8(&body_buf).unwrap_or("unable to read error body");
+        Err(anyhow::anyhow!(
+            "Failed to upload file. Status: {:?}, Body: {}",
+            response.status(),
+            error_body
+        ))
+    }
+}
@@ -0,0 +1,66 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use cid::Cid;
+use serde::Deserialize;
+use wstd::http::{IntoBody, Request};
+
+use super::{multipart_body, send, StorageBackend};
+use crate::ipfs::decode_ipfs_cid;
+
+/// Upload files through Pinata's v3 files API.
+pub struct PinataStorage {
+    /// Upload endpoint.
+    pub api_url: String,
+    api_key: String,
+}
+
+impl PinataStorage {
+    pub fn new(api_url: &str, api_key: &str) -> Self {
+        Self { api_url: api_url.to_string(), api_key: api_key.to_string() }
+    }
+}
+
+#[async_trait(?Send)]
+impl StorageBackend for PinataStorage {
+    fn get_name(&self) -> &str {
+        "pinata"
+    }
+
+    async fn upload(&self, data: &[u8], name: &str) -> Result<Cid> {
+        eprintln!("Uploading file to Pinata: {}", name);
+
+        // define multipart request boundary
+        let boundary = "----RustBoundary";
+        let request_body = multipart_body(boundary, name, data, &[("network", "public")]);
+
+        let request = Request::post(&self.api_url)
+            .header("Authorization", &format!("Bearer {}", self.api_key))
+            .header("Content-Type", &format!("multipart/form-data; boundary={}", boundary))
+            .body(request_body.into_body())?;
+
+        let (_, body_buf) = send(request).await?;
+
+        // Parse using Pinata's response format
+        #[derive(Debug, Deserialize)]
+        struct PinataResponse {
+            data: PinataData,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct PinataData {
+            cid: String,
+        }
+
+        let hash = serde_json::from_slice::<PinataResponse>(&body_buf)
+            .map_err(|_| {
+                anyhow::anyhow!(
+                    "Could not extract hash from response: {}",
+                    String::from_utf8_lossy(&body_buf)
+                )
+            })?
+            .data
+            .cid;
+
+        decode_ipfs_cid(&hash).map_err(|e| anyhow::anyhow!("Failed to decode IPFS CID: {}", e))
+    }
+}
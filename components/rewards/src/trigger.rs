@@ -8,12 +8,30 @@ use alloy_sol_types::SolValue;
 use anyhow::Result;
 use wavs_wasi_utils::decode_event_log_data;
 
-pub fn decode_trigger_event(trigger_data: TriggerData) -> Result<u64> {
+/// A decoded trigger.
+pub struct Trigger {
+    /// The trigger ID.
+    pub id: u64,
+    /// The chain name and block height of the triggering event, if any.
+    pub block: Option<(String, u64)>,
+    /// The scheduled time of a cron trigger, in seconds since the Unix epoch.
+    pub timestamp: Option<u64>,
+}
+
+pub fn decode_trigger_event(trigger_data: TriggerData) -> Result<Trigger> {
     match trigger_data {
-        TriggerData::Cron(TriggerDataCron { trigger_time }) => Ok(trigger_time.nanos),
-        TriggerData::EvmContractEvent(TriggerDataEvmContractEvent { log, .. }) => {
+        TriggerData::Cron(TriggerDataCron { trigger_time }) => {
+            let timestamp = trigger_time.nanos / 1_000_000_000;
+            Ok(Trigger { id: trigger_time.nanos, block: None, timestamp: Some(timestamp) })
+        }
+        TriggerData::EvmContractEvent(TriggerDataEvmContractEvent {
+            log,
+            chain_name,
+            block_height,
+            ..
+        }) => {
             let solidity::WavsRewardsTrigger { triggerId } = decode_event_log_data!(log)?;
-            Ok(triggerId)
+            Ok(Trigger { id: triggerId, block: Some((chain_name, block_height)), timestamp: None })
         }
         _ => Err(anyhow::anyhow!("Unsupported trigger data type")),
     }
@@ -9,6 +9,13 @@ export interface PendingReward {
   reward: string;
   claimable: string;
   proof: string[];
+  breakdown?: SourceReward[];
+}
+
+export interface SourceReward {
+  source: string;
+  index: number;
+  amount: string;
 }
 
 export interface MerkleTreeData {
@@ -16,7 +23,9 @@ export interface MerkleTreeData {
   metadata: {
     num_accounts: number;
     reward_token_address: string;
-    total_rewards: string;
+    // Totals keyed by reward token address, as big number strings.
+    total_rewards: { [token: string]: string };
+    epoch_rewards: { [token: string]: string };
     sources: {
       name: string;
       metadata: {
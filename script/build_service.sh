@@ -35,7 +35,7 @@ REWARD_SOURCE_NFT_ADDR=`jq -r '.reward_source_nft' "./.docker/rewards_deploy.jso
 REWARDS_TRIGGER_EVENT="WavsRewardsTrigger(uint64)"
 REWARDS_CRON_SCHEDULE="0 0 * * * *"
 REWARDS_ENV_VARS="WAVS_ENV_PINATA_API_URL,WAVS_ENV_PINATA_API_KEY"
-REWARDS_CONFIG="reward_token=${REWARD_TOKEN_ADDR},reward_source_nft=${REWARD_SOURCE_NFT_ADDR}"
+REWARDS_CONFIG="reward_token=${REWARD_TOKEN_ADDR},reward_source_nft=${REWARD_SOURCE_NFT_ADDR},reward_distributor=${REWARD_DISTRIBUTOR_ADDR}"
 
 BASE_CMD="docker run --rm --network host -w /data -v $(pwd):/data ghcr.io/lay3rlabs/wavs:0.4.0-rc wavs-cli service --json true --home /data --file /data/${FILE_LOCATION}"
 
@@ -98,6 +98,13 @@ function new_workflow() {
 }
 
 # === Rewards (event trigger AND cron schedule) ===
+# Every run needs a snapshot block per chain that all operators agree on.
+# Event triggers use the block of the triggering event. Cron triggers carry no
+# block, so each chain is snapshotted at its last block at or before the
+# scheduled trigger time. That chain must already have a later block, so keep
+# it producing blocks (e.g. `anvil --block-time 1` locally) or the cron run
+# fails. Don't set `snapshot_blocks` for the cron workflow: a fixed block would
+# be read again on every run.
 new_workflow ${REWARD_DISTRIBUTOR_ADDR} ${REWARD_DISTRIBUTOR_ADDR} "event" ${REWARDS_TRIGGER_EVENT} ${REWARDS_ENV_VARS} ${REWARDS_CONFIG}
 new_workflow ${REWARD_DISTRIBUTOR_ADDR} ${REWARD_DISTRIBUTOR_ADDR} "cron" "${REWARDS_CRON_SCHEDULE}" ${REWARDS_ENV_VARS} ${REWARDS_CONFIG}
 